@@ -1,216 +1,5998 @@
-use anyhow::Result;
-use chrono::{Datelike, NaiveDate};
-use clap::Parser;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+use clap::{CommandFactory, Parser, ValueEnum};
+use isocountry::CountryCode;
 use itertools::Itertools;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use ureq::OrAnyStatus;
 
-use std::{env, time::Duration};
+mod continents;
+mod timezones;
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, Read, Write},
+    num::NonZeroUsize,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 // The Abstract API returns more than just public holidays - these are the ones we're sure we want to include.
 // Originally we filtered out disallowed types (assuming it was preferable to fail-open and include if we're
 // not sure) but it turns out there's too many types to reasonably manage.
 const ALLOWED_HOLIDAY_TYPES: &[&str] = &["National", "Local holiday"];
 
+// Types that only apply to part of a country (e.g. a state or province).
+const REGIONAL_HOLIDAY_TYPES: &[&str] = &["Local holiday"];
+
 fn main() {
-    let args: Args = Args::parse();
+    let mut args: Args = Args::parse();
 
-    let abstract_api_key = require_from_env("ABSTRACT_API_KEY");
-    let slack_webhook_url = require_from_env("SLACK_WEBHOOK_URL");
+    if let Err(e) = check_out(&args) {
+        e.exit();
+    }
 
-    let date = match args.date {
-        Some(date) => chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d")
-            .expect("Invalid date argument (expected YYYY-MM-DD)"),
-        None => chrono::Local::now().date_naive(),
-    };
+    if args.validate_config {
+        let problems = validate_config(&args);
+        if problems.is_empty() {
+            match args.error_format {
+                ErrorFormat::Text => println!("configuration OK"),
+                ErrorFormat::Json => eprintln!("{}", ureq::json!({ "event": "config_ok" })),
+            }
+            return;
+        }
 
-    println!("sending holidays for {}", date);
+        for problem in problems.iter() {
+            report_error(args.error_format, &anyhow::format_err!("{}", problem));
+        }
+        std::process::exit(1);
+    }
 
-    let country_codes = args.countries.split(',');
+    if args.sample_payload {
+        print_sample_payload(&args);
+        return;
+    }
 
-    let holidays: Vec<Holiday> = country_codes
-        .flat_map(|cc| {
-            let from_api = fetch_holidays_from_abstract(&abstract_api_key, cc, date);
-            match from_api {
-                Ok(results) => results,
-                Err(e) => {
-                    println!("warning: error fetching holidays for country {}", cc);
-                    println!("{}", e);
-                    Vec::new()
+    let missing_env_vars = required_env_vars(&args)
+        .into_iter()
+        .filter(|(key, _)| env::var(key).is_err())
+        .collect_vec();
+    if !missing_env_vars.is_empty() {
+        match args.error_format {
+            ErrorFormat::Text => {
+                eprintln!("error: missing required environment variables:");
+                for (key, help) in missing_env_vars {
+                    eprintln!("  {}: {}", key, help);
                 }
             }
-        })
-        .collect();
+            ErrorFormat::Json => eprintln!(
+                "{}",
+                ureq::json!({
+                    "event": "error",
+                    "message": format!(
+                        "missing required environment variables: {}",
+                        missing_env_vars.iter().map(|(key, _)| key).join(", ")
+                    ),
+                    "missing_env_vars": missing_env_vars
+                        .iter()
+                        .map(|(key, help)| ureq::json!({ "name": key, "help": help }))
+                        .collect_vec(),
+                })
+            ),
+        }
+        std::process::exit(1);
+    }
 
-    for h in holidays.iter() {
-        println!("{:?}", h);
+    if let Err(e) = add_jobs(&mut args) {
+        report_error(args.error_format, &e);
+        std::process::exit(1);
     }
 
-    send_to_slack(&slack_webhook_url, holidays).unwrap();
+    if args.test_webhook {
+        let sent = require_from_env("SLACK_WEBHOOK_URL").and_then(|slack_webhook_url| {
+            send_test_message(
+                &slack_webhook_url,
+                Duration::from_secs(args.slack_timeout_secs),
+                args.max_response_bytes,
+                args.error_format,
+            )
+            .context("failed to send test message to Slack")
+        });
+        match (sent, args.error_format) {
+            (Ok(()), ErrorFormat::Text) => println!("test message sent to Slack"),
+            (Ok(()), ErrorFormat::Json) => {
+                eprintln!("{}", ureq::json!({ "event": "test_message_sent" }))
+            }
+            (Err(e), format) => {
+                report_error(format, &e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let abstract_api_key = match require_from_env("ABSTRACT_API_KEY") {
+        Ok(abstract_api_key) => abstract_api_key,
+        Err(e) => {
+            report_error(args.error_format, &e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(interval_secs) = args.interval_secs {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_handler = shutdown.clone();
+        ctrlc::set_handler(move || shutdown_handler.store(true, Ordering::SeqCst))
+            .expect("failed to set signal handler");
+
+        run_on_interval(
+            &args,
+            Duration::from_secs(interval_secs),
+            &shutdown,
+            |date| {
+                // Errors are reported by the run itself, and the next run might well succeed.
+                let _ = run(&args, &abstract_api_key, date);
+            },
+        );
+        return;
+    }
+
+    if run(&args, &abstract_api_key, run_date(&args)).is_err() {
+        std::process::exit(1);
+    }
 }
 
-#[derive(Parser)]
-struct Args {
-    #[arg(long)]
-    #[arg(help("date to fetch in ISO8601 format (defaults to current day)"))]
-    date: Option<String>,
-    #[arg(help("comma-separated list of countries to fetch in 2-letter format (ISO 3166-1 alpha-2, e.g. \"US,UK,AU\")"))]
-    countries: String,
+// Checks --out against --output, which clap can't do as they're both only sometimes a problem.
+fn check_out(args: &Args) -> Result<(), clap::Error> {
+    let conflict =
+        |message| Err(Args::command().error(clap::error::ErrorKind::ArgumentConflict, message));
+
+    let Some(out) = args.out.as_deref() else {
+        return Ok(());
+    };
+    if !args.output.contains(&Output::Jsonl) && !args.output.contains(&Output::SlackJson) {
+        return conflict("--out is only used with --output jsonl or slack-json");
+    }
+    // Each output truncates the file when it's opened, so the second one would replace the first.
+    if out != "-"
+        && args.output.contains(&Output::Jsonl)
+        && args.output.contains(&Output::SlackJson)
+    {
+        return conflict(
+            "--output jsonl and slack-json can't both be written to the same --out file",
+        );
+    }
+
+    Ok(())
 }
 
-const ABSTRACT_HOLIDAYS_API_URL: &str = "https://holidays.abstractapi.com/v1/";
+// Runs until `shutdown` is set (by a signal), working out the current date afresh each time. A signal
+// during a run lets it finish (so we never stop half way through posting) and exits before the next one.
+fn run_on_interval(
+    args: &Args,
+    interval: Duration,
+    shutdown: &AtomicBool,
+    mut run_once: impl FnMut(NaiveDate),
+) {
+    while !shutdown.load(Ordering::SeqCst) {
+        let date = now(args).date_naive();
+        if matches!(args.error_format, ErrorFormat::Text) {
+            eprintln!("starting scheduled run for {}", date);
+        }
 
-fn fetch_holidays_from_abstract(
-    api_key: &str,
-    country: &str,
+        run_once(date);
+
+        // Sleep in short steps so a signal doesn't have to wait out the whole interval.
+        let next_run = Instant::now() + interval;
+        while !shutdown.load(Ordering::SeqCst) && Instant::now() < next_run {
+            std::thread::sleep(Duration::from_millis(250).min(next_run - Instant::now()));
+        }
+    }
+
+    if matches!(args.error_format, ErrorFormat::Text) {
+        eprintln!("shutting down");
+    }
+}
+
+fn run(args: &Args, abstract_api_key: &str, date: NaiveDate) -> Result<()> {
+    let dates_by_country = dates_by_country(args, date);
+    let mut retries_left = args.retry_budget;
+
+    let flush_every = match args.flush_every {
+        Some(flush_every) => flush_every.get(),
+        None => {
+            return run_countries(
+                args,
+                abstract_api_key,
+                date,
+                &dates_by_country,
+                &mut retries_left,
+            )
+        }
+    };
+
+    // Later batches are still sent after one fails, as they would be with separate runs.
+    let batches = dates_by_country.chunks(flush_every).collect_vec();
+    let mut failed_batches = 0;
+    for batch in batches.iter() {
+        if run_countries(args, abstract_api_key, date, batch, &mut retries_left).is_err() {
+            failed_batches += 1;
+        }
+    }
+
+    if failed_batches == 0 {
+        Ok(())
+    } else {
+        Err(anyhow::format_err!(
+            "{} of {} batches failed",
+            failed_batches,
+            batches.len()
+        ))
+    }
+}
+
+// The date holidays are fetched for, unless a country has its own.
+fn run_date(args: &Args) -> NaiveDate {
+    args.date.unwrap_or_else(|| now(args).date_naive())
+}
+
+// Countries can be given their own date, and weekends differ between countries, so each country has its
+// own dates. The same country can also appear more than once with different dates.
+fn dates_by_country(args: &Args, date: NaiveDate) -> Vec<(String, Vec<NaiveDate>)> {
+    args.countries
+        .iter()
+        .take(args.limit_countries.unwrap_or(usize::MAX))
+        .map(|request| {
+            let date = match (request.date, args.local_dates && args.date.is_none()) {
+                (Some(date), _) => date,
+                (None, true) => match country_today(&request.country, args) {
+                    Some(today) => today,
+                    None => {
+                        report_unknown_timezone(args.error_format, &request.country, date);
+                        date
+                    }
+                },
+                (None, false) => date,
+            };
+            let dates = if args.this_month {
+                days_in_month(date)
+            } else if args.this_weekend {
+                upcoming_weekend(date, weekend_for(&request.country))
+            } else {
+                vec![date]
+            };
+            (request.country.clone(), dates)
+        })
+        .collect()
+}
+
+// Fetches and sends holidays for the given countries, then reports how it went.
+fn run_countries(
+    args: &Args,
+    abstract_api_key: &str,
     date: NaiveDate,
-) -> Result<Vec<Holiday>> {
-    let result = ureq::get(ABSTRACT_HOLIDAYS_API_URL)
-        .query("api_key", api_key)
-        .query("country", country)
-        .query("year", date.year_ce().1.to_string().as_str())
-        .query("month", (date.month0() + 1).to_string().as_str())
-        .query("day", (date.day0() + 1).to_string().as_str())
-        .call()?
-        .into_json::<Vec<Holiday>>()?
-        .into_iter()
-        .filter(|h| {
-            h.r#type
-                .as_ref()
-                .is_some_and(|t| ALLOWED_HOLIDAY_TYPES.iter().any(|tt| t == tt))
+    dates_by_country: &[(String, Vec<NaiveDate>)],
+    // Shared between batches with --flush-every.
+    retries_left: &mut u32,
+) -> Result<()> {
+    let outcome = fetch_and_send(args, abstract_api_key, date, dates_by_country, retries_left)?;
+    report_outcome(args, &outcome);
+    outcome.result
+}
+
+// What fetching and sending did, for the summary at the end of a run.
+struct RunOutcome {
+    result: Result<()>,
+    countries: usize,
+    failed_countries: usize,
+    failed_fetches: usize,
+    holidays: usize,
+    // Status of each message posted to Slack.
+    slack_statuses: Vec<u16>,
+    elapsed: Duration,
+}
+
+// Errors before anything is fetched (e.g. a broken --custom-holidays file) are reported straight away
+// and returned, as there's nothing to summarise.
+fn fetch_and_send(
+    args: &Args,
+    abstract_api_key: &str,
+    date: NaiveDate,
+    dates_by_country: &[(String, Vec<NaiveDate>)],
+    retries_left: &mut u32,
+) -> Result<RunOutcome> {
+    let started = Instant::now();
+
+    let period = if args.this_month {
+        Some(format!("in {}", date.format("%B")))
+    } else if args.this_weekend {
+        Some("this weekend".to_string())
+    } else {
+        None
+    };
+
+    // Only structured output goes to stderr in JSON mode, so it can be parsed line by line.
+    let log_text = matches!(args.error_format, ErrorFormat::Text);
+
+    if log_text {
+        let all_dates = dates_by_country.iter().flat_map(|(_, dates)| dates);
+        match (all_dates.clone().min(), all_dates.max()) {
+            (Some(first), Some(last)) if first != last => {
+                eprintln!("fetching holidays for {} to {}", first, last)
+            }
+            _ => eprintln!("fetching holidays for {}", date),
+        }
+    }
+
+    // Files are loaded before fetching anything, so that a broken one doesn't use up any requests.
+    let custom_holidays = args
+        .custom_holidays
+        .as_deref()
+        .map(|path| {
+            load_custom_holidays(path)
+                .with_context(|| format!("failed to load custom holidays file {}", path))
         })
-        .map(|mut h| {
-            h.drop_empty_string_values();
-            h
+        .transpose();
+    let custom_holidays = match custom_holidays {
+        Ok(custom_holidays) => custom_holidays,
+        Err(e) => {
+            report_error(args.error_format, &e);
+            return Err(e);
+        }
+    };
+    let names = args
+        .names_file
+        .as_deref()
+        .map(|path| {
+            load_holiday_names(path)
+                .with_context(|| format!("failed to load holiday names file {}", path))
         })
-        .collect_vec();
+        .transpose();
+    let names = match names {
+        Ok(names) => names,
+        Err(e) => {
+            report_error(args.error_format, &e);
+            return Err(e);
+        }
+    };
 
-    // From what I've observed, the rate limit for the free tier of the API is 1 req/s.
-    // The helpful AI built in to their documentation couldn't confirm that, though.
-    // Sleeping for a second here is simple and should do the trick.
-    std::thread::sleep(Duration::from_secs(1));
+    let Fetched {
+        mut holidays,
+        failed_fetches,
+        failed_countries,
+    } = fetch_holidays(args, abstract_api_key, dates_by_country, retries_left);
 
-    Ok(result)
-}
+    let too_many_failures = args
+        .warn_threshold
+        .is_some_and(|t| t.exceeded(failed_countries.len(), dates_by_country.len()));
+    if too_many_failures {
+        match args.error_format {
+            ErrorFormat::Text => eprintln!(
+                "WARNING: {} of {} countries failed, more than the --warn-threshold allows",
+                failed_countries.len(),
+                dates_by_country.len()
+            ),
+            ErrorFormat::Json => eprintln!(
+                "{}",
+                ureq::json!({
+                    "event": "warn_threshold_exceeded",
+                    "failed_countries": failed_countries.len(),
+                    "countries": dates_by_country.len(),
+                })
+            ),
+        }
+    }
 
-#[derive(Deserialize, Debug)]
-struct Holiday {
-    name: String,
-    name_local: Option<String>,
-    country: Option<String>,
-    location: Option<String>,
-    r#type: Option<String>,
-}
+    if let Some(custom_holidays) = custom_holidays {
+        holidays.extend(matching_custom_holidays(
+            &custom_holidays,
+            dates_by_country,
+            args,
+        ));
+    }
+    let mut holidays = filter_holidays(&holidays, names.as_ref(), args);
 
-impl Holiday {
-    fn drop_empty_string_values(&mut self) {
-        self.name_local = self.name_local.as_ref().filter(|v| !v.is_empty()).cloned();
-        self.country = self.country.as_ref().filter(|v| !v.is_empty()).cloned();
-        self.location = self.location.as_ref().filter(|v| !v.is_empty()).cloned();
+    // Stable, so holidays on the same day keep the order they were fetched in.
+    holidays.sort_by_key(|h| h.date);
+
+    // Codes aren't checked up front (e.g. "UK" works with the API), but names can't be looked up for
+    // them, so they're shown as the code instead.
+    for country in holidays
+        .iter()
+        .filter_map(|h| h.country.as_deref())
+        .unique()
+    {
+        if CountryCode::for_alpha2_caseless(country).is_ok() {
+            continue;
+        }
+        match args.error_format {
+            ErrorFormat::Text => eprintln!(
+                "warning: {:?} isn't a known country code, so it's shown as is where a country name is needed",
+                country
+            ),
+            ErrorFormat::Json => eprintln!(
+                "{}",
+                ureq::json!({
+                    "event": "unknown_country",
+                    "country": country,
+                })
+            ),
+        }
+    }
+
+    if log_text {
+        for h in holidays.iter() {
+            eprintln!("{:?}", h);
+        }
     }
-}
 
-fn require_from_env(key: &str) -> String {
-    env::var(key).unwrap_or_else(|_| panic!("missing required environment variable: {}", key))
+    let holiday_count = holidays.len();
+
+    let changes = args
+        .post_only_if_changed
+        .as_deref()
+        .map(|path| compare_with_last_run(path, date, &holidays))
+        .transpose();
+
+    // Status of each message posted to Slack.
+    let mut slack_statuses = Vec::new();
+
+    let result = match (changes, args.diff_against.as_deref()) {
+        (Err(e), _) => Err(e),
+        (Ok(_), Some(path)) => diff_against_snapshot(path, &holidays),
+        (Ok(Some(RunChanges::Unchanged)), None) => {
+            if log_text {
+                eprintln!("holidays haven't changed since the last run, not sending them again");
+            }
+            Ok(())
+        }
+        (Ok(changes), None) => {
+            let message_options = message_options(
+                args,
+                dates_by_country,
+                period,
+                changes.and_then(RunChanges::correction),
+            );
+
+            // Every output is tried, even after one fails, so that one broken output doesn't hold up the rest.
+            let mut failures = Vec::new();
+            for output in args.output.iter().unique() {
+                let sent = send_output(
+                    *output,
+                    &holidays,
+                    &message_options,
+                    args,
+                    &mut io::stdout(),
+                    &mut io::stderr(),
+                    &mut slack_statuses,
+                );
+                if let Err(e) = sent {
+                    failures.push((output, e));
+                }
+            }
+
+            match failures.len() {
+                0 => Ok(()),
+                1 if args.output.iter().unique().count() == 1 => Err(failures.remove(0).1),
+                _ => Err(anyhow::format_err!(
+                    "{} of {} outputs failed: {}",
+                    failures.len(),
+                    args.output.iter().unique().count(),
+                    failures
+                        .iter()
+                        .map(|(output, e)| format!("{}: {:#}", output.name(), e))
+                        .join("; ")
+                )),
+            }
+        }
+    };
+
+    // Holidays are still sent, so the countries that did work aren't held up.
+    let result = result.and_then(|()| {
+        if too_many_failures && args.fail_over_threshold {
+            Err(anyhow::format_err!(
+                "{} of {} countries failed to fetch",
+                failed_countries.len(),
+                dates_by_country.len()
+            ))
+        } else {
+            Ok(())
+        }
+    });
+
+    let result = match (result, &args.post_only_if_changed) {
+        // Other outputs don't change what the Slack channel has seen.
+        (Ok(()), Some(path)) if posts_to_slack(args) => save_run_state(path, date, &holidays),
+        (result, _) => result,
+    };
+
+    Ok(RunOutcome {
+        result,
+        countries: dates_by_country.len(),
+        failed_countries: failed_countries.len(),
+        failed_fetches,
+        holidays: holiday_count,
+        slack_statuses,
+        elapsed: started.elapsed(),
+    })
 }
 
-fn send_to_slack(webhook_url: &str, holidays: Vec<Holiday>) -> Result<()> {
-    if holidays.is_empty() {
-        return Ok(());
+fn report_outcome(args: &Args, outcome: &RunOutcome) {
+    let result = &outcome.result;
+
+    if args.result_json {
+        println!("{}", result_json(args, outcome));
     }
 
-    let mut message_blocks = Vec::new();
-    message_blocks.push(ureq::json!(
-        {
-            "type": "header",
-            "text": {
-                "type": "plain_text",
-                "text": ":calendar: Holidays",
-                "emoji": true
+    match args.error_format {
+        ErrorFormat::Text => {
+            if let Err(e) = result {
+                eprintln!("error: {:#}", e);
             }
+            eprintln!("{}", summary_text(outcome));
         }
-    ));
+        ErrorFormat::Json => {
+            eprintln!(
+                "{}",
+                ureq::json!({
+                    "event": "result",
+                    "success": result.is_ok(),
+                    "countries": outcome.countries,
+                    "failed_countries": outcome.failed_countries,
+                    "holidays": outcome.holidays,
+                    "failed_fetches": outcome.failed_fetches,
+                    "elapsed_ms": outcome.elapsed.as_millis() as u64,
+                    "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+                })
+            );
+        }
+    }
+}
 
-    let binding = holidays.iter().into_group_map_by(|h| h.location.clone());
-    let mut holidays_by_location: Vec<(&Option<String>, &Vec<&Holiday>)> = binding.iter().collect();
+// For --result-json. The HTTP status is the last one Slack gave, whether or not it was an error.
+fn result_json(args: &Args, outcome: &RunOutcome) -> serde_json::Value {
+    let result = &outcome.result;
+    let http_status = outcome.slack_statuses.last().copied().or_else(|| {
+        let e = result.as_ref().err()?;
+        e.downcast_ref::<SlackStatusError>().map(|e| e.0)
+    });
 
-    holidays_by_location.sort_by_key(|(location, _)| *location);
+    ureq::json!({
+        "success": result.is_ok(),
+        "outputs": args.output.iter().unique().map(|o| o.name()).collect_vec(),
+        "holidays": outcome.holidays,
+        "messages_sent": outcome.slack_statuses.len(),
+        "http_status": http_status,
+        "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+    })
+}
 
-    for (location, holidays) in holidays_by_location {
-        if let Some(location) = location {
-            message_blocks.push(ureq::json!(
-                {
-                    "type": "section",
-                    "text": {
-                        "type": "mrkdwn",
-                        "text": format!("_{}_", location),
-                    }
-                }
-            ));
+fn summary_text(outcome: &RunOutcome) -> String {
+    format!(
+        "summary: fetched {} countries ({} succeeded, {} failed), found {} holidays, took {:.1}s",
+        outcome.countries,
+        outcome.countries - outcome.failed_countries,
+        outcome.failed_countries,
+        outcome.holidays,
+        outcome.elapsed.as_secs_f64()
+    )
+}
 
-            let holiday_lines: Vec<serde_json::Value> = holidays
+// What fetching found for a run, and what failed along the way.
+struct Fetched {
+    holidays: Vec<Holiday>,
+    failed_fetches: usize,
+    // Indexes into dates_by_country, for countries with at least one failed fetch.
+    failed_countries: HashSet<usize>,
+}
+
+// Fetches each country's dates, keeping the holidays of the types wanted for that country.
+fn fetch_holidays(
+    args: &Args,
+    abstract_api_key: &str,
+    dates_by_country: &[(String, Vec<NaiveDate>)],
+    retries_left: &mut u32,
+) -> Fetched {
+    // Keyed by ISO code, so that types given for GB apply to UK too and the other way around.
+    let types_by_country: HashMap<&str, &Vec<String>> = args
+        .types_for
+        .iter()
+        .map(|(country, types)| (iso_country_code(country), types))
+        .collect();
+
+    let request_delay = Duration::from_millis(args.request_delay_ms);
+    let fetch_timeout = Duration::from_secs(args.fetch_timeout_secs);
+
+    let mut failed_fetches = 0;
+    let mut failed_countries = HashSet::new();
+
+    let holidays = dates_by_country
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (cc, dates))| {
+            if i > 0 && !request_delay.is_zero() {
+                std::thread::sleep(request_delay);
+            }
+
+            let allowed_types = match types_by_country.get(iso_country_code(&cc.to_uppercase())) {
+                Some(types) => types.iter().map(String::as_str).collect_vec(),
+                None => ALLOWED_HOLIDAY_TYPES.to_vec(),
+            };
+
+            let observance = args
+                .apply_observance_rules
+                .then(|| observance_rule(cc))
+                .flatten();
+            // Weekend days with holidays that would be observed on one of the dates being fetched.
+            let observed_from = observance.map_or_else(Vec::new, |rule| rule.source_dates(dates));
+
+            let results = dates
                 .iter()
-                .map(|h| {
-                    let mut elements: Vec<serde_json::Value> = Vec::new();
+                .chain(observed_from.iter())
+                .flat_map(|date| {
+                    let fetched = fetch_with_retries(args, cc, *date, retries_left, || {
+                        fetch_holidays_from_abstract(
+                            &args.holidays_api_url,
+                            abstract_api_key,
+                            cc,
+                            *date,
+                            fetch_timeout,
+                            args.max_response_bytes,
+                        )
+                    });
 
-                    elements.push(ureq::json!({
-                        "type": "text",
-                        "text": h.name,
-                        "style": {
-                            "bold": true
+                    match fetched {
+                        Ok(results) => results
+                            .into_iter()
+                            .filter(|h| {
+                                let (included, reason) = type_filter(h, &allowed_types);
+                                if args.explain {
+                                    explain_holiday(args.error_format, h, included, &reason);
+                                }
+                                included
+                            })
+                            .collect_vec(),
+                        Err(e) => {
+                            report_fetch_error(args.error_format, cc, *date, &e);
+                            failed_fetches += 1;
+                            failed_countries.insert(i);
+                            Vec::new()
                         }
-                    }));
-
-                    if let Some(name_local) = &h.name_local {
-                        elements.push(ureq::json!({
-                            "type": "text",
-                            "text": format!(" ({})", name_local),
-                            "style": {
-                                "italic": true
-                            }
-                        }));
                     }
-
-                    ureq::json!({
-                        "type": "rich_text_section",
-                        "elements": elements,
-                    })
                 })
-                .collect();
+                .collect_vec();
 
-            message_blocks.push(ureq::json!(
-                {
-                    "type": "rich_text",
-                    "elements": [
-                        {
-                        "type": "rich_text_list",
-                        "style": "bullet",
-                        "elements": holiday_lines,
-                    }]
+            match observance {
+                Some(rule) => rule.apply(results, dates),
+                None => results,
+            }
+        })
+        .collect();
+
+    Fetched {
+        holidays,
+        failed_fetches,
+        failed_countries,
+    }
+}
+
+// Whether a holiday is one of the allowed types, and why, for --explain.
+fn type_filter(h: &Holiday, allowed_types: &[&str]) -> (bool, String) {
+    match &h.r#type {
+        Some(t) if holiday_type_in(t, allowed_types) => {
+            (true, format!("type {:?} is one of the included types", t))
+        }
+        Some(t) => (
+            false,
+            format!("type {:?} isn't one of: {}", t, allowed_types.join(", ")),
+        ),
+        None => (false, "it has no type".to_string()),
+    }
+}
+
+// Retries failures that might go away on their own, for as long as the run's --retry-budget lasts.
+fn fetch_with_retries(
+    args: &Args,
+    country: &str,
+    date: NaiveDate,
+    retries_left: &mut u32,
+    fetch: impl Fn() -> Result<Vec<Holiday>, HolidayError>,
+) -> Result<Vec<Holiday>, HolidayError> {
+    let mut from_api = fetch();
+    while let Err(e) = &from_api {
+        if *retries_left == 0 || !is_retryable(e) {
+            break;
+        }
+        *retries_left -= 1;
+        if matches!(args.error_format, ErrorFormat::Text) {
+            eprintln!(
+                "retrying country {} on {} ({} retries left)",
+                country, date, retries_left
+            );
+        }
+        let delay = match e {
+            HolidayError::RateLimited {
+                retry_after: Some(retry_after),
+            } => *retry_after,
+            _ => RETRY_DELAY,
+        };
+        std::thread::sleep(delay);
+        from_api = fetch();
+    }
+    from_api
+}
+
+// Custom holidays are only included for the countries and dates being fetched, like the API's.
+fn matching_custom_holidays(
+    custom_holidays: &[Holiday],
+    dates_by_country: &[(String, Vec<NaiveDate>)],
+    args: &Args,
+) -> Vec<Holiday> {
+    custom_holidays
+        .iter()
+        .filter(|h| {
+            let included = h.country.as_ref().is_some_and(|c| {
+                dates_by_country
+                    .iter()
+                    .any(|(cc, dates)| c.eq_ignore_ascii_case(cc) && dates.contains(&h.date))
+            });
+            if args.explain {
+                let reason = if included {
+                    "custom holiday for a country and date being fetched"
+                } else {
+                    "custom holiday for a country or date that isn't being fetched"
+                };
+                explain_holiday(args.error_format, h, included, reason);
+            }
+            included
+        })
+        .cloned()
+        .collect()
+}
+
+// Applies the filters that don't depend on what was fetched: --only-regional, --skip-dates and
+// --names-file (already loaded, as `names`).
+fn filter_holidays(
+    holidays: &[Holiday],
+    names: Option<&HashSet<String>>,
+    args: &Args,
+) -> Vec<Holiday> {
+    holidays
+        .iter()
+        .filter(|h| {
+            let regional = h.is_regional();
+            if args.only_regional && !regional {
+                if args.explain {
+                    explain_holiday(
+                        args.error_format,
+                        h,
+                        false,
+                        "it's nationwide and --only-regional is set",
+                    );
                 }
-            ))
+                return false;
+            }
+
+            if args.skip_dates.contains(&h.date) {
+                if args.explain {
+                    explain_holiday(args.error_format, h, false, "its date is in --skip-dates");
+                }
+                return false;
+            }
+
+            let listed = names.is_none_or(|names| {
+                [Some(&h.name), h.name_local.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .any(|name| names.contains(&name.trim().to_lowercase()))
+            });
+            if !listed && args.explain {
+                explain_holiday(
+                    args.error_format,
+                    h,
+                    false,
+                    "its name isn't in --names-file",
+                );
+            }
+            listed
+        })
+        .cloned()
+        .collect()
+}
+
+// How many days of previous runs the --post-only-if-changed state file remembers.
+const RUN_STATE_DAYS: i64 = 31;
+
+// The holidays each previous run found, by the date it ran for (YYYY-MM-DD).
+type RunState = HashMap<String, Vec<Holiday>>;
+
+enum RunChanges {
+    // Nothing was found for this date before, so there's nothing to correct.
+    New,
+    Unchanged,
+    Changed {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+impl RunChanges {
+    // What changed, e.g. "added Boxing Day (AU)", for MessageOptions::correction.
+    fn correction(self) -> Option<String> {
+        match self {
+            RunChanges::New | RunChanges::Unchanged => None,
+            RunChanges::Changed { added, removed } => {
+                let mut changes = Vec::new();
+                if !added.is_empty() {
+                    changes.push(format!("added {}", added.join(", ")));
+                }
+                if !removed.is_empty() {
+                    changes.push(format!("removed {}", removed.join(", ")));
+                }
+                Some(changes.join("; "))
+            }
         }
     }
+}
 
-    let message = ureq::json!({
-        "blocks": message_blocks,
-    });
+fn load_run_state(path: &str) -> Result<RunState> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse state file {}", path)),
+        // Nothing has run yet.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(RunState::new()),
+        Err(e) => Err(e).with_context(|| format!("failed to read state file {}", path)),
+    }
+}
 
-    // println!("{}", serde_json::to_string_pretty(&message).unwrap());
+fn compare_with_last_run(path: &str, date: NaiveDate, holidays: &[Holiday]) -> Result<RunChanges> {
+    let previous = load_run_state(path)?
+        .remove(&date.to_string())
+        .unwrap_or_default();
+    if previous.is_empty() {
+        return Ok(RunChanges::New);
+    }
 
-    let resp = ureq::post(webhook_url)
-        .send_json(&message)
-        .or_any_status()?;
+    let label = |h: &Holiday| format!("{} ({})", h.name, h.country.as_deref().unwrap_or("??"));
+    let previous = previous.iter().map(label).collect::<HashSet<_>>();
+    let current = holidays.iter().map(label).collect::<HashSet<_>>();
 
-    if resp.status() >= 400 {
-        println!(
-            "Warning: slack request failed (status {})",
-            resp.status_text()
-        );
-        println!("request\n{}\n", serde_json::to_string_pretty(&message)?);
-        println!("response\n{}\n", resp.into_string()?);
-        return Err(anyhow::format_err!("request to Slack API failed"));
+    if previous == current {
+        return Ok(RunChanges::Unchanged);
     }
 
-    Ok(())
+    Ok(RunChanges::Changed {
+        added: current.difference(&previous).cloned().sorted().collect(),
+        removed: previous.difference(&current).cloned().sorted().collect(),
+    })
+}
+
+fn save_run_state(path: &str, date: NaiveDate, holidays: &[Holiday]) -> Result<()> {
+    let mut state = load_run_state(path)?;
+    state.retain(|run_date, _| {
+        NaiveDate::parse_from_str(run_date, "%Y-%m-%d")
+            .is_ok_and(|d| (date - d).num_days().abs() <= RUN_STATE_DAYS)
+    });
+    state.insert(date.to_string(), holidays.to_vec());
+
+    fs::write(path, serde_json::to_string(&state)?)
+        .with_context(|| format!("failed to write state file {}", path))
+}
+
+// Prints each holiday that's been added, removed or changed since the snapshot to stdout, e.g.
+// "added: 2025-12-26 Boxing Day (Australia)", and fails if there are any.
+fn diff_against_snapshot(path: &str, holidays: &[Holiday]) -> Result<()> {
+    let snapshot =
+        load_custom_holidays(path).with_context(|| format!("failed to load snapshot {}", path))?;
+
+    // Other fields can change without it being a different holiday.
+    let key = |h: &Holiday| {
+        (
+            h.date,
+            h.country.as_deref().unwrap_or_default().to_uppercase(),
+            h.location.clone().unwrap_or_default(),
+            h.name.clone(),
+        )
+    };
+    let describe = |h: &Holiday| {
+        let place = h
+            .location
+            .as_deref()
+            .or(h.country.as_deref())
+            .unwrap_or("??");
+        format!("{} {} ({})", h.date, h.name, place)
+    };
+    let expected: HashMap<_, &Holiday> = snapshot.iter().map(|h| (key(h), h)).collect();
+    let actual: HashMap<_, &Holiday> = holidays.iter().map(|h| (key(h), h)).collect();
+
+    let mut differences = Vec::new();
+    for (k, h) in actual.iter() {
+        match expected.get(k) {
+            None => differences.push((k, format!("added: {}", describe(h)))),
+            Some(old) => {
+                let field = |name: &str, old: &Option<String>, new: &Option<String>| {
+                    let value =
+                        |v: &Option<String>| v.clone().unwrap_or_else(|| "none".to_string());
+                    (old != new).then(|| format!("{} {:?} -> {:?}", name, value(old), value(new)))
+                };
+                let changes = [
+                    field("local name", &old.name_local, &h.name_local),
+                    field("type", &old.r#type, &h.r#type),
+                    field("language", &old.language, &h.language),
+                ]
+                .into_iter()
+                .flatten()
+                .collect_vec();
+                if !changes.is_empty() {
+                    differences.push((
+                        k,
+                        format!("changed: {}: {}", describe(h), changes.join(", ")),
+                    ));
+                }
+            }
+        }
+    }
+    for (k, h) in expected.iter() {
+        if !actual.contains_key(k) {
+            differences.push((k, format!("removed: {}", describe(h))));
+        }
+    }
+
+    for (_, difference) in differences.iter().sorted() {
+        println!("{}", difference);
+    }
+
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::format_err!(
+            "{} differences from snapshot {}",
+            differences.len(),
+            path
+        ))
+    }
+}
+
+// Example holidays for --sample-payload, covering several countries, types and languages.
+fn sample_holidays() -> Vec<Holiday> {
+    let holiday = |name: &str,
+                   name_local: &str,
+                   country: &str,
+                   location: &str,
+                   r#type: &str,
+                   language: &str,
+                   date: NaiveDate| Holiday {
+        name: name.to_string(),
+        name_local: Some(name_local.to_string()),
+        country: Some(country.to_string()),
+        location: Some(location.to_string()),
+        r#type: Some(r#type.to_string()),
+        language: Some(language.to_string()),
+        date,
+        last_date: None,
+    };
+    let christmas = NaiveDate::from_ymd_opt(2025, 12, 25).unwrap();
+    let boxing_day = NaiveDate::from_ymd_opt(2025, 12, 26).unwrap();
+
+    vec![
+        holiday(
+            "Christmas Day",
+            "Christmas Day",
+            "AU",
+            "Australia",
+            "National",
+            "en",
+            christmas,
+        ),
+        holiday(
+            "Boxing Day",
+            "Boxing Day",
+            "AU",
+            "Australia",
+            "National",
+            "en",
+            boxing_day,
+        ),
+        holiday(
+            "Christmas Day",
+            "Weihnachten",
+            "DE",
+            "Germany",
+            "National",
+            "de",
+            christmas,
+        ),
+        holiday(
+            "St. Stephen's Day",
+            "Sant Esteve",
+            "ES",
+            "Spain - Catalonia",
+            "Local holiday",
+            "ca",
+            boxing_day,
+        ),
+    ]
+}
+
+fn print_sample_payload(args: &Args) {
+    let holidays = sample_holidays();
+    let dates_by_country = holidays
+        .iter()
+        .into_group_map_by(|h| h.country.clone().unwrap_or_default())
+        .into_iter()
+        .map(|(country, holidays)| (country, holidays.iter().map(|h| h.date).unique().collect()))
+        .sorted()
+        .collect_vec();
+    let options = message_options(args, &dates_by_country, None, None);
+
+    for message in slack_messages(&holidays, &options) {
+        println!("{}", serde_json::to_string_pretty(&message).unwrap());
+    }
+}
+
+fn message_options(
+    args: &Args,
+    dates_by_country: &[(String, Vec<NaiveDate>)],
+    period: Option<String>,
+    correction: Option<String>,
+) -> MessageOptions {
+    MessageOptions {
+        style: args.style,
+        show_dates: args.always_show_date
+            || dates_by_country
+                .iter()
+                .flat_map(|(_, dates)| dates)
+                .unique()
+                .count()
+                > 1,
+        period,
+        name_style: args.name_style,
+        // Plain messages are the minimal theme, with any emoji in the user's own text taken out too.
+        theme: if args.plain {
+            Theme::Minimal
+        } else {
+            args.theme
+        },
+        link_template: args.link_source.clone(),
+        name_language: args.name_lang.clone(),
+        collapse_single_country: args.collapse_single_country,
+        show_types: args.show_types,
+        country_groups: match args.group_by {
+            GroupBy::Country => args.country_group.clone(),
+            GroupBy::Continent => continents::CONTINENTS
+                .iter()
+                .map(|(continent, countries)| {
+                    let countries = countries.iter().map(|c| c.to_string()).collect();
+                    (continent.to_string(), countries)
+                })
+                .collect(),
+        },
+        terse_text: args.terse.clone(),
+        flat_chronological: args.flat_chronological,
+        footer_text: args.footer_text.clone(),
+        group_consecutive: args.group_consecutive,
+        country_emoji: args.country_emoji.iter().cloned().collect(),
+        plain: args.plain,
+        message_per_country: args.message_per_country,
+        compare: args.compare.then(|| dates_by_country.to_vec()),
+        primary_name: args.primary_name,
+        correction,
+        compat: args.compat,
+        home_country: args.home.clone(),
+        country_names: args.country_name.iter().cloned().collect(),
+        country_display: args.country_display,
+        type_labels: args.type_label.clone(),
+        type_order: args.type_order.clone(),
+        calendar_links: args.add_to_calendar_links,
+    }
+}
+
+// Sends the holidays to one output. Only data outputs (--output jsonl and slack-json) write to stdout, so
+// it can be piped somewhere; everything else, including --dry-run's payloads, goes to stderr.
+fn send_output(
+    output: Output,
+    holidays: &[Holiday],
+    options: &MessageOptions,
+    args: &Args,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+    slack_statuses: &mut Vec<u16>,
+) -> Result<()> {
+    match output {
+        Output::Slack => {
+            if let Some(path) = &args.preview_html {
+                write_html_preview(holidays, options, path, args.error_format)
+            } else if args.dry_run {
+                dry_run_slack(holidays, options, args.error_format, stderr)
+            } else {
+                require_from_env("SLACK_WEBHOOK_URL").and_then(|slack_webhook_url| {
+                    send_to_slack(&slack_webhook_url, holidays, options, args, slack_statuses)
+                })
+            }
+        }
+        Output::Jsonl => write_jsonl(holidays, args.out.as_deref(), stdout),
+        Output::SlackJson => write_slack_json(
+            holidays,
+            options,
+            args.out.as_deref(),
+            args.error_format,
+            stdout,
+        ),
+    }
+}
+
+fn write_jsonl(holidays: &[Holiday], out: Option<&str>, stdout: &mut dyn Write) -> Result<()> {
+    let mut out = open_output(out, stdout)?;
+    for h in holidays {
+        writeln!(out, "{}", serde_json::to_string(h)?)?;
+    }
+
+    Ok(())
+}
+
+fn write_slack_json(
+    holidays: &[Holiday],
+    options: &MessageOptions,
+    out: Option<&str>,
+    format: ErrorFormat,
+    stdout: &mut dyn Write,
+) -> Result<()> {
+    let messages = slack_messages(holidays, options);
+    if messages.is_empty() {
+        if matches!(format, ErrorFormat::Text) {
+            eprintln!("no holidays, nothing would be sent");
+        }
+        return Ok(());
+    }
+
+    let mut out = open_output(out, stdout)?;
+    for message in messages {
+        writeln!(out, "{}", serde_json::to_string_pretty(&message)?)?;
+    }
+
+    Ok(())
+}
+
+// Output goes to stdout unless there's a file path ("-" also means stdout).
+fn open_output<'a>(path: Option<&str>, stdout: &'a mut dyn Write) -> Result<Box<dyn Write + 'a>> {
+    match path {
+        None | Some("-") => Ok(Box::new(stdout)),
+        Some(path) => {
+            if let Some(parent) = Path::new(path).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Ok(Box::new(File::create(path)?))
+        }
+    }
+}
+
+fn report_fetch_error(format: ErrorFormat, country: &str, date: NaiveDate, e: &HolidayError) {
+    match format {
+        ErrorFormat::Text => {
+            eprintln!(
+                "warning: error fetching holidays for country {} on {}",
+                country, date
+            );
+            eprintln!("{}", e);
+        }
+        ErrorFormat::Json => {
+            eprintln!(
+                "{}",
+                ureq::json!({
+                    "event": "fetch_error",
+                    "country": country,
+                    "date": date,
+                    "kind": error_kind(e),
+                    "message": format!("{:#}", e),
+                })
+            );
+        }
+    }
+}
+
+// For errors that stop a run before it gets anywhere.
+fn report_error(format: ErrorFormat, e: &anyhow::Error) {
+    match format {
+        ErrorFormat::Text => eprintln!("error: {:#}", e),
+        ErrorFormat::Json => eprintln!(
+            "{}",
+            ureq::json!({
+                "event": "error",
+                "message": format!("{:#}", e),
+            })
+        ),
+    }
+}
+
+// Used between retries, unless the API says how long to wait.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// Rate limiting, server errors and network problems might go away on their own; anything else won't.
+fn is_retryable(e: &HolidayError) -> bool {
+    match e {
+        HolidayError::RateLimited { .. } | HolidayError::Timeout | HolidayError::Transport(_) => {
+            true
+        }
+        HolidayError::Status(status) => *status >= 500,
+        HolidayError::NotFound | HolidayError::Parse(_) => false,
+    }
+}
+
+// Why a holiday was or wasn't included, for --explain.
+fn explain_holiday(format: ErrorFormat, h: &Holiday, kept: bool, reason: &str) {
+    eprintln!("{}", explanation(format, h, kept, reason));
+}
+
+fn explanation(format: ErrorFormat, h: &Holiday, kept: bool, reason: &str) -> String {
+    let country = h.country.as_deref().unwrap_or("??");
+
+    match format {
+        ErrorFormat::Text => format!(
+            "explain: {} on {} for {}: {} ({})",
+            h.name,
+            h.date,
+            country,
+            if kept { "kept" } else { "dropped" },
+            reason
+        ),
+        ErrorFormat::Json => ureq::json!({
+            "event": "explain",
+            "country": country,
+            "date": h.date.format("%Y-%m-%d").to_string(),
+            "name": h.name,
+            "kept": kept,
+            "reason": reason,
+        })
+        .to_string(),
+    }
+}
+
+fn error_kind(e: &HolidayError) -> &'static str {
+    match e {
+        HolidayError::NotFound => "not_found",
+        HolidayError::Timeout => "timeout",
+        HolidayError::Transport(_) => "transport",
+        HolidayError::Status(_) => "http_status",
+        HolidayError::Parse(_) => "parse",
+        HolidayError::RateLimited { .. } => "rate_limited",
+    }
+}
+
+#[derive(Parser)]
+struct Args {
+    #[arg(long, value_parser = parse_iso_date)]
+    #[arg(help("date to fetch in ISO8601 format (defaults to current day)"))]
+    date: Option<NaiveDate>,
+    #[arg(required_unless_present_any([
+        "test_webhook",
+        "validate_config",
+        "jobs_file",
+        "sample_payload"
+    ]))]
+    #[arg(value_delimiter = ',', value_parser = parse_country_request)]
+    #[arg(help("comma-separated list of countries to fetch, either in 2-letter format (ISO 3166-1 alpha-2, e.g. \"US,UK,AU\") or by name (e.g. \"Australia\"), optionally with a date to use for that country (e.g. \"US@2025-07-04\")"))]
+    countries: Vec<CountryRequest>,
+    #[arg(long)]
+    #[arg(help(
+        "JSON file of additional holidays to include, in the same format as the Abstract API"
+    ))]
+    custom_holidays: Option<String>,
+    #[arg(long)]
+    #[arg(help("include the date on every holiday line"))]
+    always_show_date: bool,
+    #[arg(long, value_name = "COUNTRY:TYPES", value_parser = parse_country_types)]
+    #[arg(help("holiday types to include for one country instead of the defaults, e.g. \"DE:National,Local holiday\" (repeatable)"))]
+    types_for: Vec<(String, Vec<String>)>,
+    #[arg(long)]
+    #[arg(help("post a test message to the Slack webhook and exit"))]
+    test_webhook: bool,
+    #[arg(long, value_enum, default_value_t = NameStyle::Auto)]
+    #[arg(help("which holiday names to show"))]
+    name_style: NameStyle,
+    #[arg(long, default_value_t = 0)]
+    #[arg(help("extra delay between fetching each country, in milliseconds"))]
+    request_delay_ms: u64,
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Output::Slack])]
+    #[arg(help(
+        "where to send the holidays, comma-separated to use more than one (e.g. \"slack,jsonl\")"
+    ))]
+    output: Vec<Output>,
+    #[arg(long)]
+    #[arg(help("only include holidays for part of a country, not nationwide ones"))]
+    only_regional: bool,
+    #[arg(long, value_delimiter = ',', value_parser = parse_iso_date)]
+    #[arg(help("comma-separated list of dates (YYYY-MM-DD) to leave out holidays for"))]
+    skip_dates: Vec<NaiveDate>,
+    #[arg(long, value_enum, default_value_t = Theme::Default)]
+    #[arg(help("look and feel of the Slack message"))]
+    theme: Theme,
+    #[arg(long, default_value_t = 30)]
+    #[arg(help("timeout for each request to the holidays API, in seconds"))]
+    fetch_timeout_secs: u64,
+    #[arg(long, default_value_t = 10)]
+    #[arg(help("timeout for posting to Slack, in seconds"))]
+    slack_timeout_secs: u64,
+    #[arg(long)]
+    #[arg(help("include holidays for the whole month that the date falls in"))]
+    this_month: bool,
+    #[arg(long, conflicts_with("this_month"))]
+    #[arg(help("include holidays for the weekend on or after the date (Saturday and Sunday in most countries)"))]
+    this_weekend: bool,
+    #[arg(long, conflicts_with("date"))]
+    #[arg(help(
+        "keep running, fetching and sending holidays for the current day every this many seconds"
+    ))]
+    interval_secs: Option<u64>,
+    #[arg(long, value_name = "PATH")]
+    #[arg(help("file to record the last message sent in, so that retries don't post the same message twice"))]
+    idempotency_file: Option<String>,
+    #[arg(long, value_name = "PATH")]
+    #[arg(help(
+        "write --output jsonl or slack-json to this file instead of stdout (\"-\" for stdout)"
+    ))]
+    out: Option<String>,
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    #[arg(help("format of errors and the final result written to stderr"))]
+    error_format: ErrorFormat,
+    #[arg(long, value_enum, default_value_t = Style::List)]
+    #[arg(help("layout of each location's holidays in the Slack message"))]
+    style: Style,
+    #[arg(long, value_name = "URL_TEMPLATE", num_args = 0..=1, require_equals = true, default_missing_value = NAGER_COUNTRY_URL_TEMPLATE)]
+    #[arg(help("link each country to more information, optionally with a URL where {country} is replaced by the country code (defaults to Nager.Date)"))]
+    link_source: Option<String>,
+    #[arg(long, value_name = "LANGUAGE")]
+    #[arg(help("show holiday names in this language where available (e.g. \"en\", \"de\"), instead of following --name-style"))]
+    name_lang: Option<String>,
+    #[arg(long)]
+    #[arg(help("leave out the location heading when all holidays are for the same place"))]
+    collapse_single_country: bool,
+    #[arg(long)]
+    #[arg(help("print the Slack message to stderr and check it, instead of sending it"))]
+    dry_run: bool,
+    #[arg(long)]
+    #[arg(help("show each holiday's type (e.g. \"National\") next to its name"))]
+    show_types: bool,
+    #[arg(long, value_name = "REGION=COUNTRIES", value_parser = parse_country_group)]
+    #[arg(help("group countries under a region heading, e.g. \"APAC=AU,NZ,JP\" (repeatable, other countries are grouped under \"Other\")"))]
+    country_group: Vec<(String, Vec<String>)>,
+    #[arg(long, value_name = "TEXT", num_args = 0..=1, require_equals = true, default_missing_value = DEFAULT_TERSE_TEXT)]
+    #[arg(help("post just a short message when there are any holidays, instead of listing them"))]
+    terse: Option<String>,
+    #[arg(long)]
+    #[arg(help("check the configuration, without fetching or sending anything, and exit"))]
+    validate_config: bool,
+    #[arg(long, value_enum, default_value_t = GroupBy::Country, conflicts_with("country_group"))]
+    #[arg(help("how to group holidays in the Slack message"))]
+    group_by: GroupBy,
+    #[arg(long, value_name("PATH"))]
+    #[arg(help("write an HTML preview of the Slack message to this file instead of sending it"))]
+    preview_html: Option<String>,
+    #[arg(long, conflicts_with_all(["country_group", "group_by"]))]
+    #[arg(help("list every holiday in date order, instead of grouping them by location"))]
+    flat_chronological: bool,
+    #[arg(long, value_name("TEXT"))]
+    #[arg(help("note to add at the end of every message, in Slack markdown"))]
+    footer_text: Option<String>,
+    #[arg(long, default_value_t = 0, value_name("RETRIES"))]
+    #[arg(help("total number of times failed requests to the holidays API can be retried, shared across the whole run"))]
+    retry_budget: u32,
+    #[arg(long)]
+    #[arg(help("explain why each holiday was kept or left out"))]
+    explain: bool,
+    #[arg(long, value_name("N"))]
+    #[arg(help("only fetch the first N countries, e.g. for quickly testing a long list"))]
+    limit_countries: Option<usize>,
+    #[arg(long)]
+    #[arg(help("show holidays that last several days once, with their first and last day"))]
+    group_consecutive: bool,
+    #[arg(long, value_name = "COUNTRY=EMOJI", value_parser = parse_country_emoji)]
+    #[arg(help(
+        "emoji to show next to a country instead of its flag, e.g. \"AU=:kangaroo:\" (repeatable)"
+    ))]
+    country_emoji: Vec<(String, String)>,
+    #[arg(long, conflicts_with_all(["theme", "country_emoji"]))]
+    #[arg(help(
+        "post a plain text message without any emoji or styling, e.g. for screen readers"
+    ))]
+    plain: bool,
+    #[arg(long)]
+    #[arg(help("send a separate message for each country, instead of one for all of them"))]
+    message_per_country: bool,
+    #[arg(long, conflicts_with_all(["flat_chronological", "message_per_country", "terse"]))]
+    #[arg(help("show which countries are off and which are working on each date, instead of listing holidays by location"))]
+    compare: bool,
+    #[arg(long, value_enum, default_value_t = PrimaryName::English)]
+    #[arg(help(
+        "which name to show first, in bold, when both the English and local names are shown"
+    ))]
+    primary_name: PrimaryName,
+    #[arg(long, value_name("STATE_FILE"))]
+    #[arg(help("file to record each run's holidays in, so they're only sent again when they change (with a correction note)"))]
+    post_only_if_changed: Option<String>,
+    #[arg(long, value_enum, conflicts_with_all(["compare", "flat_chronological", "style"]))]
+    #[arg(help(
+        "format the message for a Slack-compatible chat server that doesn't support Block Kit"
+    ))]
+    compat: Option<Compat>,
+    #[arg(long, value_name("COUNTRY"), value_parser = resolve_country_code)]
+    #[arg(help("home country, whose holidays are shown first and marked as home"))]
+    home: Option<String>,
+    #[arg(long, value_name("PATH"))]
+    #[arg(help("file listing the holiday names to include, one per line (English or local names, any case)"))]
+    names_file: Option<String>,
+    #[arg(long, default_value_t = 5 * 1024 * 1024, value_name("BYTES"))]
+    #[arg(help("largest response to accept from the holidays API or Slack"))]
+    max_response_bytes: u64,
+    #[arg(long, value_name = "COUNTRY=NAME", value_parser = parse_country_name)]
+    #[arg(help("name to show for a country instead of its official one, e.g. \"KR=South Korea\" (repeatable)"))]
+    country_name: Vec<(String, String)>,
+    #[arg(long, value_enum, default_value_t = CountryDisplay::Full)]
+    #[arg(help("how to show country names in the message"))]
+    country_display: CountryDisplay,
+    #[arg(long)]
+    #[arg(help("also show holidays that fall on a weekend on the weekday they're observed on, in countries with a usual rule for it (AU, CA, GB, IE, NZ, US)"))]
+    apply_observance_rules: bool,
+    #[arg(long, value_name("PATH"))]
+    #[arg(help("JSON file of countries to fetch, e.g. [{\"country\": \"AU\", \"date\": \"2025-01-26\"}], in addition to any given as arguments"))]
+    jobs_file: Option<String>,
+    #[arg(long, conflicts_with("date"))]
+    #[arg(help(
+        "use each country's current date, in its main time zone, instead of the date here"
+    ))]
+    local_dates: bool,
+    #[arg(long, value_name = "COUNTRY=TIMEZONE", value_parser = parse_timezone, requires("local_dates"))]
+    #[arg(help("time zone to use for a country with --local-dates, e.g. \"US=America/Los_Angeles\" (repeatable)"))]
+    timezone: Vec<(String, Tz)>,
+    #[arg(long)]
+    #[arg(help("print a JSON summary of what was sent to stdout at the end, e.g. for scripts"))]
+    result_json: bool,
+    #[arg(long, value_name = "TYPE=LABEL", value_parser = parse_type_label)]
+    #[arg(help("label to show for a holiday type with --show-types, e.g. \"Local holiday=Regional\" (repeatable)"))]
+    type_label: Vec<(String, String)>,
+    #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+    #[arg(help("comma-separated holiday types, in the order to show holidays on the same day in (other types come last)"))]
+    type_order: Vec<String>,
+    #[arg(long, value_name = "N|PERCENT", value_parser = parse_failure_threshold)]
+    #[arg(help("warn loudly when more than this many countries (or this percentage, e.g. \"25%\") fail to fetch"))]
+    warn_threshold: Option<FailureThreshold>,
+    #[arg(long, requires("warn_threshold"))]
+    #[arg(help(
+        "exit with an error when --warn-threshold is exceeded, after sending whatever was found"
+    ))]
+    fail_over_threshold: bool,
+    #[arg(long)]
+    #[arg(help(
+        "print the Slack message for a built-in set of example holidays to stdout and exit, e.g. to see how the other options look"
+    ))]
+    sample_payload: bool,
+    #[arg(long)]
+    #[arg(help("add a link to each holiday for adding it to Google Calendar"))]
+    add_to_calendar_links: bool,
+    #[arg(long, value_name = "DATETIME", value_parser = parse_now, hide = true)]
+    #[arg(help(
+        "use this as the current time instead of the system clock, e.g. \"2025-12-25T09:00:00+11:00\" (for testing)"
+    ))]
+    now: Option<DateTime<FixedOffset>>,
+    #[arg(long, value_name = "PATH")]
+    #[arg(help(
+        "compare the holidays found with a JSON file of expected ones, in the same format as --custom-holidays, instead of sending them, and exit with an error if they differ"
+    ))]
+    diff_against: Option<String>,
+    #[arg(long, value_name = "URL", default_value = ABSTRACT_HOLIDAYS_API_URL, hide = true)]
+    #[arg(help("fetch holidays from this URL instead of the Abstract API (for testing)"))]
+    holidays_api_url: String,
+    // Options that keep track of a whole run can't be split into batches.
+    #[arg(long, value_name = "N")]
+    #[arg(conflicts_with_all([
+        "post_only_if_changed",
+        "diff_against",
+        "out",
+        "idempotency_file",
+        "preview_html",
+        "result_json",
+        "warn_threshold",
+    ]))]
+    #[arg(help(
+        "send holidays after fetching every N countries, instead of once at the end, e.g. for long lists (--retry-budget is shared between batches)"
+    ))]
+    flush_every: Option<NonZeroUsize>,
+}
+
+const DEFAULT_TERSE_TEXT: &str = ":palm_tree: Holiday today in some regions";
+
+const NAGER_COUNTRY_URL_TEMPLATE: &str = "https://date.nager.at/PublicHoliday/Country/{country}";
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum GroupBy {
+    /// one section per location, optionally grouped with --country-group
+    Country,
+    /// locations grouped under the continent they're on
+    Continent,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Style {
+    /// a heading followed by a bulleted list
+    List,
+    /// two columns, with the location next to its holidays
+    Fields,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ErrorFormat {
+    /// human-readable warnings
+    Text,
+    /// one JSON object per line, for automation
+    Json,
+}
+
+// Countries where the weekend isn't Saturday and Sunday. Everywhere else uses Saturday and Sunday.
+const WEEKENDS: &[(&str, [Weekday; 2])] = &[
+    ("BD", [Weekday::Fri, Weekday::Sat]),
+    ("BH", [Weekday::Fri, Weekday::Sat]),
+    ("DZ", [Weekday::Fri, Weekday::Sat]),
+    ("EG", [Weekday::Fri, Weekday::Sat]),
+    ("IL", [Weekday::Fri, Weekday::Sat]),
+    ("IQ", [Weekday::Fri, Weekday::Sat]),
+    ("JO", [Weekday::Fri, Weekday::Sat]),
+    ("KW", [Weekday::Fri, Weekday::Sat]),
+    ("LY", [Weekday::Fri, Weekday::Sat]),
+    ("OM", [Weekday::Fri, Weekday::Sat]),
+    ("QA", [Weekday::Fri, Weekday::Sat]),
+    ("SA", [Weekday::Fri, Weekday::Sat]),
+    ("SD", [Weekday::Fri, Weekday::Sat]),
+    ("SY", [Weekday::Fri, Weekday::Sat]),
+    ("YE", [Weekday::Fri, Weekday::Sat]),
+];
+
+// Where holidays falling on a weekend are observed instead, for --apply-observance-rules. This is the
+// usual rule in each country, it doesn't cover every holiday (or two holidays in one weekend).
+const OBSERVANCE_RULES: &[(&str, ObservanceRule)] = &[
+    ("AU", ObservanceRule::new(Weekday::Mon, Weekday::Mon)),
+    ("CA", ObservanceRule::new(Weekday::Mon, Weekday::Mon)),
+    ("GB", ObservanceRule::new(Weekday::Mon, Weekday::Mon)),
+    ("IE", ObservanceRule::new(Weekday::Mon, Weekday::Mon)),
+    ("NZ", ObservanceRule::new(Weekday::Mon, Weekday::Mon)),
+    ("US", ObservanceRule::new(Weekday::Fri, Weekday::Mon)),
+];
+
+#[derive(Clone, Copy)]
+struct ObservanceRule {
+    saturday: Weekday,
+    sunday: Weekday,
+}
+
+impl ObservanceRule {
+    const fn new(saturday: Weekday, sunday: Weekday) -> Self {
+        ObservanceRule { saturday, sunday }
+    }
+
+    // The nearby weekday a weekend holiday is observed on.
+    fn observed_date(self, date: NaiveDate) -> Option<NaiveDate> {
+        let observed_on = match date.weekday() {
+            Weekday::Sat => self.saturday,
+            Weekday::Sun => self.sunday,
+            _ => return None,
+        };
+
+        (-2..=2i64)
+            .filter_map(|offset| date.checked_add_signed(chrono::Duration::days(offset)))
+            .find(|d| d.weekday() == observed_on)
+    }
+
+    // Weekend days that aren't in `dates`, but have holidays that would be observed on one of them.
+    fn source_dates(self, dates: &[NaiveDate]) -> Vec<NaiveDate> {
+        dates
+            .iter()
+            .flat_map(|date| {
+                (-2..=2i64)
+                    .filter_map(|offset| date.checked_add_signed(chrono::Duration::days(offset)))
+            })
+            .filter(|d| !dates.contains(d))
+            .filter(|d| {
+                self.observed_date(*d)
+                    .is_some_and(|observed| dates.contains(&observed))
+            })
+            .unique()
+            .collect()
+    }
+
+    // Adds an "observed" holiday for each weekend holiday observed on one of `dates`, and drops the
+    // holidays fetched only for that.
+    fn apply(self, mut holidays: Vec<Holiday>, dates: &[NaiveDate]) -> Vec<Holiday> {
+        let observed = holidays
+            .iter()
+            .filter_map(|h| {
+                let observed_date = self.observed_date(h.date)?;
+                dates.contains(&observed_date).then(|| Holiday {
+                    name: format!("{} (observed)", h.name),
+                    name_local: h.name_local.as_ref().map(|n| format!("{} (observed)", n)),
+                    date: observed_date,
+                    ..h.clone()
+                })
+            })
+            .collect_vec();
+
+        holidays.retain(|h| dates.contains(&h.date));
+        holidays.extend(observed);
+        holidays
+    }
+}
+
+fn observance_rule(country: &str) -> Option<ObservanceRule> {
+    let country = iso_country_code(country);
+    OBSERVANCE_RULES
+        .iter()
+        .find(|(cc, _)| cc.eq_ignore_ascii_case(country))
+        .map(|(_, rule)| *rule)
+}
+
+// The current date in the country's main time zone, for --local-dates.
+fn country_today(country: &str, args: &Args) -> Option<NaiveDate> {
+    let timezone = match args
+        .timezone
+        .iter()
+        .find(|(cc, _)| cc.eq_ignore_ascii_case(country))
+    {
+        Some((_, timezone)) => *timezone,
+        None => timezones::TIMEZONES
+            .iter()
+            .find(|(cc, _)| cc.eq_ignore_ascii_case(iso_country_code(country)))
+            .and_then(|(_, name)| name.parse().ok())?,
+    };
+
+    Some(now(args).with_timezone(&timezone).date_naive())
+}
+
+// The current time, or the time given with --now.
+fn now(args: &Args) -> DateTime<FixedOffset> {
+    args.now.unwrap_or_else(|| Local::now().fixed_offset())
+}
+
+// Accepts an RFC 3339 datetime, or one without an offset in the local time zone.
+fn parse_now(s: &str) -> Result<DateTime<FixedOffset>> {
+    if let Ok(now) = DateTime::parse_from_rfc3339(s) {
+        return Ok(now);
+    }
+
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+        .ok()
+        .and_then(|now| Local.from_local_datetime(&now).single())
+        .map(|now| now.fixed_offset())
+        .with_context(|| {
+            format!(
+                "invalid datetime {:?} (expected e.g. 2025-12-25T09:00:00+11:00)",
+                s
+            )
+        })
+}
+
+fn report_unknown_timezone(format: ErrorFormat, country: &str, date: NaiveDate) {
+    match format {
+        ErrorFormat::Text => eprintln!(
+            "warning: no time zone is known for country {}, so it uses the date here ({}), set one with --timezone",
+            country, date
+        ),
+        ErrorFormat::Json => eprintln!(
+            "{}",
+            ureq::json!({
+                "event": "unknown_timezone",
+                "country": country,
+                "date": date,
+            })
+        ),
+    }
+}
+
+fn parse_timezone(s: &str) -> Result<(String, Tz)> {
+    let (country, timezone) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::format_err!("expected COUNTRY=TIMEZONE"))?;
+    let timezone = timezone
+        .trim()
+        .parse()
+        .map_err(|e| anyhow::format_err!("{}", e))?;
+
+    Ok((resolve_country_code(country)?.to_uppercase(), timezone))
+}
+
+fn weekend_for(country: &str) -> [Weekday; 2] {
+    let country = iso_country_code(country);
+    WEEKENDS
+        .iter()
+        .find(|(cc, _)| cc.eq_ignore_ascii_case(country))
+        .map(|(_, weekend)| *weekend)
+        .unwrap_or([Weekday::Sat, Weekday::Sun])
+}
+
+// The first day of the weekend on or after the date, and the day after it.
+fn upcoming_weekend(date: NaiveDate, weekend: [Weekday; 2]) -> Vec<NaiveDate> {
+    let days_until_weekend =
+        (weekend[0].num_days_from_monday() + 7 - date.weekday().num_days_from_monday()) % 7;
+    let first_day = date + chrono::Days::new(days_until_weekend.into());
+
+    vec![first_day, first_day + chrono::Days::new(1)]
+}
+
+fn days_in_month(date: NaiveDate) -> Vec<NaiveDate> {
+    let first = date.with_day(1).expect("every month has a first day");
+
+    first
+        .iter_days()
+        .take_while(|d| d.month() == first.month())
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+struct CountryRequest {
+    country: String,
+    // Overrides the date for just this country.
+    date: Option<NaiveDate>,
+}
+
+fn parse_country_request(s: &str) -> Result<CountryRequest> {
+    let (country, date) = match s.split_once('@') {
+        Some((country, date)) => (country, Some(parse_iso_date(date.trim())?)),
+        None => (s, None),
+    };
+
+    Ok(CountryRequest {
+        country: resolve_country_code(country)?,
+        date,
+    })
+}
+
+#[derive(Deserialize)]
+struct Job {
+    country: String,
+    date: Option<String>,
+}
+
+impl Job {
+    fn into_request(self) -> Result<CountryRequest> {
+        Ok(CountryRequest {
+            country: resolve_country_code(&self.country)?,
+            date: self.date.as_deref().map(parse_iso_date).transpose()?,
+        })
+    }
+}
+
+// A JSON array of {"country": ..., "date": ...} objects, for running many countries and dates at once.
+// Each is the same as a "COUNTRY@DATE" argument, with the date optional.
+fn load_jobs(path: &str) -> Result<Vec<CountryRequest>> {
+    let contents = fs::read_to_string(path)?;
+
+    serde_json::from_str::<Vec<Job>>(&contents)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, job)| job.into_request().with_context(|| format!("job {}", i + 1)))
+        .collect()
+}
+
+// Adds the --jobs-file jobs to the countries given as arguments, so they're all fetched in the same run.
+fn add_jobs(args: &mut Args) -> Result<()> {
+    if let Some(path) = &args.jobs_file {
+        let jobs = load_jobs(path).with_context(|| format!("failed to load jobs file {}", path))?;
+        args.countries.extend(jobs);
+    }
+
+    Ok(())
+}
+
+// Common names that don't match, or are ambiguous against, the official ISO 3166 names.
+const COUNTRY_NAME_ALIASES: &[(&str, &str)] = &[
+    ("United States", "US"),
+    ("USA", "US"),
+    ("United Kingdom", "GB"),
+    ("Great Britain", "GB"),
+    ("South Korea", "KR"),
+    ("North Korea", "KP"),
+    ("Russia", "RU"),
+    ("Vietnam", "VN"),
+    ("Iran", "IR"),
+    ("Taiwan", "TW"),
+    ("Bolivia", "BO"),
+    ("Venezuela", "VE"),
+    ("Tanzania", "TZ"),
+    ("Syria", "SY"),
+    ("Laos", "LA"),
+    ("Moldova", "MD"),
+];
+
+// The API accepts "UK" as well as the ISO code "GB", but tables of our own only have ISO codes.
+fn iso_country_code(country: &str) -> &str {
+    if country.eq_ignore_ascii_case("UK") {
+        "GB"
+    } else {
+        country
+    }
+}
+
+// Country codes are passed through as-is. Names are matched against aliases, then the official ISO
+// names, and finally the start of the official names as long as only one country matches.
+fn resolve_country_code(s: &str) -> Result<String> {
+    let s = s.trim();
+
+    if s.len() == 2 && s.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok(s.to_string());
+    }
+
+    if let Some((_, code)) = COUNTRY_NAME_ALIASES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+    {
+        return Ok(code.to_string());
+    }
+
+    if let Some(country) = CountryCode::iter().find(|c| c.name().eq_ignore_ascii_case(s)) {
+        return Ok(country.alpha2().to_string());
+    }
+
+    let prefix = s.to_lowercase();
+    let candidates = CountryCode::iter()
+        .filter(|c| c.name().to_lowercase().starts_with(&prefix))
+        .collect_vec();
+
+    match candidates.as_slice() {
+        [country] => Ok(country.alpha2().to_string()),
+        [] => Err(anyhow::format_err!("unknown country {:?}", s)),
+        _ => Err(anyhow::format_err!(
+            "ambiguous country {:?}, could be any of: {}",
+            s,
+            candidates.iter().map(|c| c.name()).join("; ")
+        )),
+    }
+}
+
+fn parse_iso_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .with_context(|| format!("invalid date {:?} (expected YYYY-MM-DD)", s))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Output {
+    /// post a message to the Slack webhook
+    Slack,
+    /// print one JSON object per holiday to stdout
+    Jsonl,
+    /// print the Slack message to stdout as JSON, without posting it
+    SlackJson,
+}
+
+impl Output {
+    // As given on the command line, e.g. "slack-json".
+    fn name(self) -> String {
+        self.to_possible_value()
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum NameStyle {
+    /// english name, followed by the local name if it's different
+    Auto,
+    /// english name, followed by the local name
+    Both,
+    /// local name only (falls back to the English name)
+    Local,
+    /// english name only
+    English,
+}
+
+fn parse_country_group(s: &str) -> Result<(String, Vec<String>)> {
+    let (region, countries) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::format_err!("expected REGION=COUNTRY[,COUNTRY...]"))?;
+    let countries = countries
+        .split(',')
+        .map(resolve_country_code)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((region.trim().to_string(), countries))
+}
+
+fn parse_country_name(s: &str) -> Result<(String, String)> {
+    let (country, name) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::format_err!("expected COUNTRY=NAME"))?;
+    if name.trim().is_empty() {
+        return Err(anyhow::format_err!("missing name for {}", country));
+    }
+
+    Ok((
+        iso_country_code(&resolve_country_code(country)?.to_uppercase()).to_string(),
+        name.trim().to_string(),
+    ))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum FailureThreshold {
+    Count(usize),
+    Percent(f64),
+}
+
+impl FailureThreshold {
+    fn exceeded(self, failed: usize, total: usize) -> bool {
+        match self {
+            FailureThreshold::Count(n) => failed > n,
+            FailureThreshold::Percent(p) => total > 0 && failed as f64 * 100.0 / total as f64 > p,
+        }
+    }
+}
+
+fn parse_failure_threshold(s: &str) -> Result<FailureThreshold> {
+    match s.trim().strip_suffix('%') {
+        Some(percent) => Ok(FailureThreshold::Percent(
+            percent
+                .trim()
+                .parse()
+                .context("expected a percentage, e.g. \"25%\"")?,
+        )),
+        None => {
+            Ok(FailureThreshold::Count(s.trim().parse().context(
+                "expected a number of countries or a percentage",
+            )?))
+        }
+    }
+}
+
+fn parse_type_label(s: &str) -> Result<(String, String)> {
+    let (holiday_type, label) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::format_err!("expected TYPE=LABEL"))?;
+
+    Ok((holiday_type.trim().to_string(), label.trim().to_string()))
+}
+
+fn parse_country_emoji(s: &str) -> Result<(String, String)> {
+    let (country, emoji) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::format_err!("expected COUNTRY=EMOJI"))?;
+    let emoji = emoji.trim().trim_matches(':');
+    if emoji.is_empty() {
+        return Err(anyhow::format_err!("missing emoji for {}", country));
+    }
+
+    Ok((
+        iso_country_code(&resolve_country_code(country)?.to_uppercase()).to_string(),
+        emoji.to_string(),
+    ))
+}
+
+fn parse_country_types(s: &str) -> Result<(String, Vec<String>)> {
+    let (country, types) = s
+        .split_once(':')
+        .ok_or_else(|| anyhow::format_err!("expected COUNTRY:TYPE[,TYPE...]"))?;
+    let types = types.split(',').map(str::to_string).collect_vec();
+
+    Ok((resolve_country_code(country)?.to_uppercase(), types))
+}
+
+const ABSTRACT_HOLIDAYS_API_URL: &str = "https://holidays.abstractapi.com/v1/";
+
+fn fetch_holidays_from_abstract(
+    api_url: &str,
+    api_key: &str,
+    country: &str,
+    date: NaiveDate,
+    timeout: Duration,
+    max_response_bytes: u64,
+) -> Result<Vec<Holiday>, HolidayError> {
+    let response = ureq::get(api_url)
+        .query("api_key", api_key)
+        .query("country", country)
+        .query("year", date.year_ce().1.to_string().as_str())
+        .query("month", (date.month0() + 1).to_string().as_str())
+        .query("day", (date.day0() + 1).to_string().as_str())
+        .timeout(timeout)
+        .call()?;
+    let body = read_response(response, max_response_bytes)?;
+
+    let result = serde_json::from_str::<Vec<Holiday>>(&body)
+        .map_err(io::Error::from)?
+        .into_iter()
+        .map(|mut h| {
+            h.drop_empty_string_values();
+            h
+        })
+        .collect_vec();
+
+    // From what I've observed, the rate limit for the free tier of the API is 1 req/s.
+    // The helpful AI built in to their documentation couldn't confirm that, though.
+    // Sleeping for a second here is simple and should do the trick.
+    std::thread::sleep(Duration::from_secs(1));
+
+    Ok(result)
+}
+
+// Why fetching holidays from the API failed.
+#[derive(Debug)]
+enum HolidayError {
+    // The API doesn't know the country.
+    NotFound,
+    // The request or response took longer than --fetch-timeout-secs.
+    Timeout,
+    // Couldn't connect to the API, or the connection broke.
+    Transport(Box<ureq::Transport>),
+    // Any other unsuccessful response.
+    Status(u16),
+    // The response wasn't the JSON we expected, or was too big.
+    Parse(io::Error),
+    // With the delay asked for in the response's Retry-After header, if any.
+    RateLimited { retry_after: Option<Duration> },
+}
+
+impl std::fmt::Display for HolidayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HolidayError::NotFound => {
+                write!(f, "holidays API didn't find the country (status 404)")
+            }
+            HolidayError::Timeout => write!(f, "request to holidays API timed out"),
+            HolidayError::Transport(e) => {
+                // Like ureq's own message, but without the query string, which has the API key in it.
+                if let Some(url) = e.url() {
+                    let mut url = url.clone();
+                    url.set_query(None);
+                    write!(f, "{}: ", url)?;
+                }
+                write!(f, "{}", e.kind())?;
+                if let Some(message) = e.message() {
+                    write!(f, ": {}", message)?;
+                }
+                if let Some(source) = std::error::Error::source(e.as_ref()) {
+                    write!(f, ": {}", source)?;
+                }
+                Ok(())
+            }
+            HolidayError::Status(status) => {
+                write!(f, "request to holidays API failed (status {})", status)
+            }
+            HolidayError::Parse(e) => write!(f, "unexpected response from holidays API: {}", e),
+            HolidayError::RateLimited { retry_after: None } => {
+                write!(f, "rate limited by holidays API (status 429)")
+            }
+            HolidayError::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "rate limited by holidays API (status 429, retry after {}s)",
+                retry_after.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HolidayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HolidayError::Transport(e) => Some(e.as_ref()),
+            HolidayError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ureq::Error> for HolidayError {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(404, _) => HolidayError::NotFound,
+            ureq::Error::Status(429, response) => HolidayError::RateLimited {
+                retry_after: response
+                    .header("retry-after")
+                    .and_then(|s| s.trim().parse().ok())
+                    .map(Duration::from_secs),
+            },
+            ureq::Error::Status(status, _) => HolidayError::Status(status),
+            ureq::Error::Transport(e) => {
+                let timed_out = std::error::Error::source(&e)
+                    .and_then(|source| source.downcast_ref::<io::Error>())
+                    .is_some_and(|source| source.kind() == io::ErrorKind::TimedOut);
+                if timed_out {
+                    HolidayError::Timeout
+                } else {
+                    HolidayError::Transport(Box::new(e))
+                }
+            }
+        }
+    }
+}
+
+// Reading the response body is the only other fallible step.
+impl From<io::Error> for HolidayError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::TimedOut {
+            HolidayError::Timeout
+        } else {
+            HolidayError::Parse(e)
+        }
+    }
+}
+
+// Reads a whole response body, unless it's suspiciously large. Errors are io::Errors (as they are
+// with ureq's own readers), so that they're reported as a bad response.
+fn read_response(response: ureq::Response, max_bytes: u64) -> io::Result<String> {
+    // Bytes rather than a string, as the limit can fall in the middle of a multi-byte character.
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(max_bytes + 1)
+        .read_to_end(&mut body)?;
+
+    if body.len() as u64 > max_bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("response is larger than the limit of {} bytes", max_bytes),
+        ));
+    }
+
+    String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Types are compared loosely, so that changes in casing or stray whitespace don't drop holidays.
+fn holiday_type_in(holiday_type: &str, types: &[&str]) -> bool {
+    let normalize = |t: &str| t.trim().to_lowercase();
+    let holiday_type = normalize(holiday_type);
+
+    types.iter().any(|t| normalize(t) == holiday_type)
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct Holiday {
+    name: String,
+    name_local: Option<String>,
+    country: Option<String>,
+    location: Option<String>,
+    r#type: Option<String>,
+    // Language of `name_local`.
+    language: Option<String>,
+    #[serde(
+        deserialize_with = "deserialize_abstract_date",
+        serialize_with = "serialize_abstract_date"
+    )]
+    date: NaiveDate,
+    // Set when the same holiday on consecutive days has been merged into one, for --group-consecutive.
+    #[serde(skip)]
+    last_date: Option<NaiveDate>,
+}
+
+// Abstract returns dates in US format (e.g. "12/25/2024").
+const ABSTRACT_DATE_FORMAT: &str = "%m/%d/%Y";
+
+fn deserialize_abstract_date<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    NaiveDate::parse_from_str(&s, ABSTRACT_DATE_FORMAT).map_err(serde::de::Error::custom)
+}
+
+fn serialize_abstract_date<S>(date: &NaiveDate, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(&date.format(ABSTRACT_DATE_FORMAT))
+}
+
+impl Holiday {
+    // e.g. "Thu 25 Dec 2025", or "Sat 25 Jan – Mon 27 Jan 2025" for a merged holiday.
+    fn display_date(&self) -> String {
+        match self.last_date {
+            Some(last_date) if last_date.year() == self.date.year() => format!(
+                "{} – {}",
+                self.date.format("%a %-d %b"),
+                last_date.format(DISPLAY_DATE_FORMAT)
+            ),
+            Some(last_date) => format!(
+                "{} – {}",
+                self.date.format(DISPLAY_DATE_FORMAT),
+                last_date.format(DISPLAY_DATE_FORMAT)
+            ),
+            None => self.date.format(DISPLAY_DATE_FORMAT).to_string(),
+        }
+    }
+
+    fn drop_empty_string_values(&mut self) {
+        self.name_local = self.name_local.as_ref().filter(|v| !v.is_empty()).cloned();
+        self.country = self.country.as_ref().filter(|v| !v.is_empty()).cloned();
+        self.location = self.location.as_ref().filter(|v| !v.is_empty()).cloned();
+        self.language = self.language.as_ref().filter(|v| !v.is_empty()).cloned();
+    }
+
+    fn is_regional(&self) -> bool {
+        self.r#type
+            .as_ref()
+            .is_some_and(|t| holiday_type_in(t, REGIONAL_HOLIDAY_TYPES))
+    }
+
+    // Returns the name to show first, and optionally a second name to show alongside it.
+    // A preferred language takes priority over the style when there's a name in that language.
+    fn display_names(&self, style: NameStyle, language: Option<&str>) -> (&str, Option<&str>) {
+        let name_local = self.name_local.as_deref();
+
+        if let Some(language) = language {
+            if language.eq_ignore_ascii_case("en") {
+                return (&self.name, None);
+            }
+            if let Some(name_local) = name_local.filter(|_| {
+                self.language
+                    .as_ref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(language))
+            }) {
+                return (name_local, None);
+            }
+        }
+
+        match style {
+            NameStyle::Auto => (&self.name, name_local.filter(|n| *n != self.name)),
+            NameStyle::Both => (&self.name, name_local),
+            NameStyle::Local => (name_local.unwrap_or(&self.name), None),
+            NameStyle::English => (&self.name, None),
+        }
+    }
+}
+
+// Custom holidays use the same schema as the API, so the same post-processing applies.
+fn load_custom_holidays(path: &str) -> Result<Vec<Holiday>> {
+    let contents = fs::read_to_string(path)?;
+    let holidays = serde_json::from_str::<Vec<Holiday>>(&contents)?
+        .into_iter()
+        .map(|mut h| {
+            h.drop_empty_string_values();
+            h
+        })
+        .collect_vec();
+
+    Ok(holidays)
+}
+
+// One holiday name per line, in English or the local language. Blank lines and lines starting with
+// "#" are ignored. Names are lower case, for comparing without case.
+fn load_holiday_names(path: &str) -> Result<HashSet<String>> {
+    let contents = fs::read_to_string(path)?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+// Checks everything that can be checked without making any requests, so that all problems can be
+// reported at once.
+fn validate_config(args: &Args) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if args.countries.is_empty() && args.jobs_file.is_none() {
+        problems.push("no countries given".to_string());
+    }
+    for request in args.countries.iter() {
+        // The API also accepts UK, which isn't an ISO code.
+        if CountryCode::for_alpha2_caseless(iso_country_code(&request.country)).is_err() {
+            problems.push(format!("unknown country code {:?}", request.country));
+        }
+    }
+
+    for (country, types) in args.types_for.iter() {
+        if CountryCode::for_alpha2_caseless(iso_country_code(country)).is_err() {
+            problems.push(format!("--types-for: unknown country code {:?}", country));
+        }
+        if types.iter().any(|t| t.trim().is_empty()) {
+            problems.push(format!("--types-for: empty holiday type for {}", country));
+        }
+    }
+
+    for (key, _) in required_env_vars(args) {
+        match env::var(key) {
+            Ok(url) if key == "SLACK_WEBHOOK_URL" && !url.starts_with("https://") => {
+                problems.push("SLACK_WEBHOOK_URL should be an https:// URL".to_string())
+            }
+            Ok(_) => {}
+            Err(_) => problems.push(format!("missing required environment variable: {}", key)),
+        }
+    }
+
+    if let Some(path) = &args.custom_holidays {
+        if let Err(e) = load_custom_holidays(path) {
+            problems.push(format!("--custom-holidays: {:#}", e));
+        }
+    }
+
+    if let Some(path) = &args.names_file {
+        if let Err(e) = load_holiday_names(path) {
+            problems.push(format!("--names-file: {:#}", e));
+        }
+    }
+
+    if let Some(path) = &args.jobs_file {
+        if let Err(e) = load_jobs(path) {
+            problems.push(format!("--jobs-file: {:#}", e));
+        }
+    }
+
+    problems
+}
+
+// Whether holidays are actually posted to Slack, rather than only shown or checked.
+fn posts_to_slack(args: &Args) -> bool {
+    args.output.contains(&Output::Slack)
+        && !args.dry_run
+        && args.preview_html.is_none()
+        && args.diff_against.is_none()
+}
+
+// Environment variables needed for this run, with a description of each for when they're missing.
+fn required_env_vars(args: &Args) -> Vec<(&'static str, &'static str)> {
+    let mut vars = Vec::new();
+
+    if !args.test_webhook {
+        vars.push((
+            "ABSTRACT_API_KEY",
+            "API key for your Abstract API account, see https://www.abstractapi.com/api/holidays-api",
+        ));
+    }
+
+    if args.test_webhook || posts_to_slack(args) {
+        vars.push((
+            "SLACK_WEBHOOK_URL",
+            "URL of a Slack \"Incoming Webhook\" integration (or use --dry-run to not post anything)",
+        ));
+    }
+
+    vars
+}
+
+fn require_from_env(key: &str) -> Result<String> {
+    env::var(key).with_context(|| format!("missing required environment variable {}", key))
+}
+
+// How dates are shown in messages, e.g. "Wed 25 Dec 2024".
+const DISPLAY_DATE_FORMAT: &str = "%a %-d %b %Y";
+
+struct MessageOptions {
+    style: Style,
+    show_dates: bool,
+    // Shown in the header when the message covers more than a day, e.g. "in March".
+    period: Option<String>,
+    name_style: NameStyle,
+    theme: Theme,
+    link_template: Option<String>,
+    name_language: Option<String>,
+    collapse_single_country: bool,
+    show_types: bool,
+    // Region names with the countries in them, in the order they should be shown.
+    country_groups: Vec<(String, Vec<String>)>,
+    // Replaces the whole message, without listing the holidays.
+    terse_text: Option<String>,
+    // One list of every holiday in date order, instead of grouping them by location.
+    flat_chronological: bool,
+    // Markdown shown in small text at the end of every message.
+    footer_text: Option<String>,
+    // Show the same holiday on consecutive days once, with its date range.
+    group_consecutive: bool,
+    // Emoji names to show next to countries instead of their flags, by upper case country code.
+    country_emoji: HashMap<String, String>,
+    // No emoji or styling at all, for screen readers and clients that show emoji badly.
+    plain: bool,
+    // Send each country's holidays in a message of its own.
+    message_per_country: bool,
+    // Which countries are off and which are working on each date, for --compare.
+    compare: Option<Vec<(String, Vec<NaiveDate>)>>,
+    // Which of the two names comes first when both are shown.
+    primary_name: PrimaryName,
+    // What changed, shown at the top of the message when it replaces an earlier one with different holidays.
+    correction: Option<String>,
+    // Use older message formatting for chat servers that only partly support Slack's.
+    compat: Option<Compat>,
+    // Shown first, and marked as home.
+    home_country: Option<String>,
+    // Names to show for countries instead of the ones from the API, by upper case country code.
+    country_names: HashMap<String, String>,
+    // How to show countries without a name of their own in country_names.
+    country_display: CountryDisplay,
+    // Labels to show for holiday types instead of the types themselves, e.g. "Local holiday" => "Regional".
+    type_labels: Vec<(String, String)>,
+    // Holidays on the same day are shown in this order of their types.
+    type_order: Vec<String>,
+    calendar_links: bool,
+}
+
+impl MessageOptions {
+    // The label from --type-label for a holiday type, or the type itself.
+    fn type_label<'a>(&'a self, holiday_type: &'a str) -> &'a str {
+        self.type_labels
+            .iter()
+            .find(|(t, _)| holiday_type_in(holiday_type, &[t]))
+            .map_or(holiday_type, |(_, label)| label)
+    }
+
+    // Where a holiday type comes in --type-order, with unlisted types after all the listed ones.
+    fn type_rank(&self, holiday_type: Option<&str>) -> usize {
+        holiday_type
+            .and_then(|h| {
+                self.type_order
+                    .iter()
+                    .position(|t| holiday_type_in(h, &[t]))
+            })
+            .unwrap_or(self.type_order.len())
+    }
+
+    // The name to show in bold and the one to show after it in italics, if any.
+    fn holiday_names<'a>(&self, h: &'a Holiday) -> (&'a str, Option<&'a str>) {
+        match h.display_names(self.name_style, self.name_language.as_deref()) {
+            (primary, Some(secondary)) if matches!(self.primary_name, PrimaryName::Local) => {
+                (secondary, Some(primary))
+            }
+            names => names,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Compat {
+    /// legacy attachments with plain text, for Mattermost and Rocket.Chat
+    Mattermost,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CountryDisplay {
+    /// the country's name, e.g. "Germany"
+    Full,
+    /// three-letter country code, e.g. "DEU"
+    Short,
+    /// two-letter country code, e.g. "DE"
+    Code,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PrimaryName {
+    /// english name first, in bold
+    English,
+    /// local name first, in bold
+    Local,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Theme {
+    /// calendar header with a bulleted list per location
+    Default,
+    /// no emoji or rich text, just plain sections
+    Minimal,
+    /// country flags and holiday emoji
+    Festive,
+}
+
+impl Theme {
+    fn header_text(self, period: Option<&str>) -> String {
+        let holidays = match period {
+            Some(period) => format!("Holidays {}", period),
+            None => "Holidays".to_string(),
+        };
+
+        match self {
+            Theme::Default => format!(":calendar: {}", holidays),
+            Theme::Minimal => holidays,
+            Theme::Festive => format!(":tada: {} :tada:", holidays),
+        }
+    }
+}
+
+// Used by the festive theme. Matched case-insensitively against the English name, first match wins.
+const HOLIDAY_EMOJI: &[(&str, &str)] = &[
+    ("christmas", "christmas_tree"),
+    ("new year", "fireworks"),
+    ("easter", "hatching_chick"),
+    ("independence", "tada"),
+    ("labour", "hammer_and_wrench"),
+    ("labor", "hammer_and_wrench"),
+];
+
+fn holiday_emoji(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    HOLIDAY_EMOJI
+        .iter()
+        .find(|(keyword, _)| name.contains(keyword))
+        .map(|(_, emoji)| *emoji)
+}
+
+// Name of the emoji to show next to a holiday's country, if any. Slack has flag emoji for each country
+// code (e.g. "flag-au"), which the festive theme uses unless there's an override.
+fn country_emoji(h: &Holiday, options: &MessageOptions) -> Option<String> {
+    let country = h.country.as_ref()?.to_uppercase();
+    let country = iso_country_code(&country);
+
+    match (options.theme, options.country_emoji.get(country)) {
+        (Theme::Minimal, _) => None,
+        (_, Some(emoji)) => Some(emoji.clone()),
+        (Theme::Festive, None) => Some(format!("flag-{}", country.to_lowercase())),
+        (Theme::Default, None) => None,
+    }
+}
+
+fn send_to_slack(
+    webhook_url: &str,
+    holidays: &[Holiday],
+    options: &MessageOptions,
+    args: &Args,
+    statuses: &mut Vec<u16>,
+) -> Result<()> {
+    let timeout = Duration::from_secs(args.slack_timeout_secs);
+    let idempotency_file = args.idempotency_file.as_deref();
+
+    let messages = slack_messages(holidays, options);
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    // println!("{}", serde_json::to_string_pretty(&message).unwrap());
+
+    let last_sent = match idempotency_file {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            // A missing file just means nothing has been sent yet.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read idempotency file {}", path))
+            }
+        },
+        None => String::new(),
+    };
+    let last_sent = last_sent.lines().map(str::trim).collect::<HashSet<_>>();
+
+    // Every message from this run that Slack has, so a retry after a partial failure only sends the rest.
+    let mut sent = Vec::new();
+    let mut posted_any = false;
+
+    for message in messages.iter() {
+        let message_hash = payload_hash(message);
+
+        if last_sent.contains(message_hash.as_str()) {
+            if matches!(args.error_format, ErrorFormat::Text) {
+                eprintln!("skipping Slack post, this message was already sent");
+            }
+        } else {
+            // Incoming webhooks are limited to about one message per second.
+            if posted_any {
+                std::thread::sleep(MESSAGE_DELAY);
+            }
+            statuses.push(post_to_slack(
+                webhook_url,
+                message,
+                timeout,
+                args.max_response_bytes,
+                args.error_format,
+            )?);
+            posted_any = true;
+        }
+
+        sent.push(message_hash);
+        if let Some(path) = idempotency_file {
+            fs::write(path, sent.join("\n"))?;
+        }
+    }
+
+    Ok(())
+}
+
+const MESSAGE_DELAY: Duration = Duration::from_secs(1);
+
+// Usually a single message, or one for each country with --message-per-country. None if there are no
+// holidays, unless there's a correction to send, e.g. because every holiday sent earlier was removed.
+fn slack_messages(holidays: &[Holiday], options: &MessageOptions) -> Vec<serde_json::Value> {
+    let mut messages = if holidays.is_empty() {
+        options
+            .correction
+            .iter()
+            .map(|correction| correction_message(correction, options))
+            .collect_vec()
+    } else if options.message_per_country {
+        holidays
+            .iter()
+            .into_group_map_by(|h| h.country.clone())
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, holidays)| {
+                let holidays = holidays.into_iter().cloned().collect_vec();
+                build_slack_payload(&holidays, options)
+            })
+            .collect_vec()
+    } else {
+        vec![build_slack_payload(holidays, options)]
+    };
+
+    // Only once, at the end of the last message.
+    if let (Some(footer), Some(last)) = (&options.footer_text, messages.last_mut()) {
+        add_footer(last, footer, options);
+    }
+
+    messages
+}
+
+// Identifies a message, so retried runs can tell whether they've already sent it.
+// 64-bit FNV-1a, which unlike std's DefaultHasher gives the same hash with every Rust release, so
+// hashes saved in the idempotency file still match after an upgrade.
+fn payload_hash(message: &serde_json::Value) -> String {
+    let hash = message
+        .to_string()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+
+    format!("{:016x}", hash)
+}
+
+fn build_slack_payload(holidays: &[Holiday], options: &MessageOptions) -> serde_json::Value {
+    if let Some(Compat::Mattermost) = options.compat {
+        return attachments_message(holidays, options);
+    }
+
+    holiday_message(holidays, options)
+}
+
+fn add_footer(message: &mut serde_json::Value, footer: &str, options: &MessageOptions) {
+    if let Some(Compat::Mattermost) = options.compat {
+        if let Some(last) = message["attachments"]
+            .as_array_mut()
+            .and_then(|attachments| attachments.last_mut())
+        {
+            last["footer"] = footer.into();
+        }
+    } else if let Some(blocks) = message["blocks"].as_array_mut() {
+        blocks.push(ureq::json!({
+            "type": "context",
+            "elements": [note_text(footer, options)]
+        }));
+    }
+}
+
+// The same message using legacy attachments and plain text, which Slack-compatible chat servers that
+// don't support Block Kit (or not all of it) can still show.
+fn attachments_message(holidays: &[Holiday], options: &MessageOptions) -> serde_json::Value {
+    if let Some(text) = &options.terse_text {
+        return ureq::json!({
+            "text": text,
+        });
+    }
+
+    let merged;
+    let holidays = if options.group_consecutive {
+        merged = merge_consecutive_days(holidays);
+        &merged[..]
+    } else {
+        holidays
+    };
+
+    let holidays = &in_type_order(holidays, options)[..];
+
+    let mut text = options.theme.header_text(options.period.as_deref());
+    if let Some(correction) = &options.correction {
+        text.push_str(&format!("\n{}", correction_text(correction, false)));
+    }
+
+    let attachments = holidays
+        .iter()
+        .into_group_map_by(|h| display_location(h, options))
+        .into_iter()
+        .filter_map(|(location, holidays)| Some((location?, holidays)))
+        .sorted_by_key(|(location, holidays)| (!is_home(holidays[0], options), location.clone()))
+        .map(|(location, holidays)| {
+            let title = match country_emoji(holidays[0], options) {
+                Some(emoji) => format!(":{}: {}", emoji, plain_location(&location, &holidays, options)),
+                None => plain_location(&location, &holidays, options),
+            };
+            let lines = holidays
+                .iter()
+                .map(|h| format!("- {}", holiday_text(h, options, false)))
+                .join("\n");
+
+            let mut attachment = ureq::json!({
+                "fallback": format!("{}: {}", location, holidays.iter().map(|h| &h.name).join(", ")),
+                "title": title,
+                "text": lines,
+            });
+            if let Some(url) = source_link(&holidays, options) {
+                attachment["title_link"] = url.into();
+            }
+            attachment
+        })
+        .collect_vec();
+
+    ureq::json!({
+        "text": text,
+        "attachments": attachments,
+    })
+}
+
+// Just the header and the correction, for when there are no holidays left to show.
+fn correction_message(correction: &str, options: &MessageOptions) -> serde_json::Value {
+    let header = options.theme.header_text(options.period.as_deref());
+
+    if let Some(Compat::Mattermost) = options.compat {
+        return ureq::json!({
+            "text": format!("{}\n{}", header, correction_text(correction, false)),
+            "attachments": [],
+        });
+    }
+
+    ureq::json!({
+        "blocks": [
+            {
+                "type": "header",
+                "text": {
+                    "type": "plain_text",
+                    "text": header,
+                    "emoji": true
+                }
+            },
+            {
+                "type": "section",
+                "text": note_text(&correction_text(correction, !options.plain), options),
+            }
+        ],
+    })
+}
+
+// Holidays are already in date order, this only changes the order of holidays on the same day.
+fn in_type_order<'a>(holidays: &'a [Holiday], options: &MessageOptions) -> Cow<'a, [Holiday]> {
+    if options.type_order.is_empty() {
+        return Cow::Borrowed(holidays);
+    }
+
+    holidays
+        .iter()
+        .sorted_by_key(|h| (h.date, options.type_rank(h.r#type.as_deref())))
+        .cloned()
+        .collect_vec()
+        .into()
+}
+
+fn correction_text(correction: &str, markdown: bool) -> String {
+    if markdown {
+        format!(
+            "*Correction* to the earlier post: {}",
+            escape_mrkdwn(correction)
+        )
+    } else {
+        format!("Correction to the earlier post: {}", correction)
+    }
+}
+
+// Text given to us to show as-is, in markdown unless the message should be plain.
+fn note_text(text: &str, options: &MessageOptions) -> serde_json::Value {
+    if options.plain {
+        ureq::json!({
+            "type": "plain_text",
+            "text": strip_emoji_codes(text),
+            "emoji": false,
+        })
+    } else {
+        ureq::json!({
+            "type": "mrkdwn",
+            "text": text,
+        })
+    }
+}
+
+// Removes emoji codes like ":palm_tree:", leaving the rest of the text (including other colons) alone.
+fn strip_emoji_codes(text: &str) -> String {
+    let is_emoji_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_+-'".contains(c))
+    };
+
+    let mut stripped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        match after.find(':') {
+            Some(end) if is_emoji_name(&after[..end]) => {
+                stripped.push_str(&rest[..start]);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                stripped.push_str(&rest[..=start]);
+                rest = after;
+            }
+        }
+    }
+    stripped.push_str(rest);
+
+    stripped
+        .lines()
+        .map(|line| line.split_whitespace().join(" "))
+        .join("\n")
+}
+
+fn holiday_message(holidays: &[Holiday], options: &MessageOptions) -> serde_json::Value {
+    let merged;
+    let holidays = if options.group_consecutive {
+        merged = merge_consecutive_days(holidays);
+        &merged[..]
+    } else {
+        holidays
+    };
+
+    let holidays = &in_type_order(holidays, options)[..];
+
+    if let Some(text) = &options.terse_text {
+        return ureq::json!({
+            "blocks": [
+                {
+                    "type": "section",
+                    "text": note_text(text, options),
+                }
+            ],
+        });
+    }
+
+    let mut message_blocks = Vec::new();
+    message_blocks.push(ureq::json!(
+        {
+            "type": "header",
+            "text": {
+                "type": "plain_text",
+                "text": options.theme.header_text(options.period.as_deref()),
+                "emoji": true
+            }
+        }
+    ));
+
+    if let Some(correction) = &options.correction {
+        message_blocks.push(ureq::json!({
+            "type": "section",
+            "text": note_text(&correction_text(correction, !options.plain), options),
+        }));
+    }
+
+    if let Some(dates_by_country) = &options.compare {
+        message_blocks.extend(compare_blocks(holidays, dates_by_country, options));
+        return ureq::json!({
+            "blocks": message_blocks,
+        });
+    }
+
+    if options.flat_chronological {
+        message_blocks.push(flat_chronological_block(holidays, options));
+        return ureq::json!({
+            "blocks": message_blocks,
+        });
+    }
+
+    let binding = holidays
+        .iter()
+        .into_group_map_by(|h| display_location(h, options));
+    let mut holidays_by_location: Vec<(&Option<String>, &Vec<&Holiday>)> = binding.iter().collect();
+
+    // The home country comes first, then everywhere else alphabetically.
+    holidays_by_location
+        .sort_by_key(|(location, holidays)| (!is_home(holidays[0], options), *location));
+
+    // A heading above a single location's list is just noise when asked to collapse it.
+    let show_headings = !(options.collapse_single_country && holidays_by_location.len() == 1);
+
+    if options.country_groups.is_empty() {
+        for (location, holidays) in holidays_by_location {
+            if let Some(location) = location {
+                message_blocks.extend(location_blocks(location, holidays, options, show_headings));
+            }
+        }
+    } else {
+        let region_of = |holidays: &[&Holiday]| {
+            options
+                .country_groups
+                .iter()
+                .find(|(_, countries)| {
+                    holidays[0].country.as_deref().is_some_and(|c| {
+                        countries.iter().any(|cc| {
+                            iso_country_code(cc).eq_ignore_ascii_case(iso_country_code(c))
+                        })
+                    })
+                })
+                .map_or(OTHER_REGION, |(region, _)| region.as_str())
+        };
+
+        let regions = options
+            .country_groups
+            .iter()
+            .map(|(region, _)| region.as_str())
+            .chain([OTHER_REGION])
+            .unique();
+
+        for region in regions {
+            let locations = holidays_by_location
+                .iter()
+                .filter(|(_, holidays)| region_of(holidays) == region)
+                .filter_map(|(location, holidays)| Some((location.as_ref()?, holidays)))
+                .collect_vec();
+
+            if locations.is_empty() {
+                continue;
+            }
+
+            message_blocks.push(region_heading(region, options));
+            for (location, holidays) in locations {
+                message_blocks.extend(location_blocks(location, holidays, options, show_headings));
+            }
+        }
+    }
+
+    ureq::json!({
+        "blocks": message_blocks,
+    })
+}
+
+// Every holiday in date order, each line starting with its date and location, e.g.
+// "Thu 25 Dec 2025, Germany: Christmas Day".
+fn flat_chronological_block(holidays: &[Holiday], options: &MessageOptions) -> serde_json::Value {
+    let holidays = holidays.iter().sorted_by_key(|h| h.date);
+    let location = |h: &Holiday| {
+        display_location(h, options)
+            .or_else(|| h.country.clone())
+            .unwrap_or_default()
+    };
+
+    match options.theme {
+        Theme::Minimal => {
+            let lines = holidays
+                .map(|h| {
+                    format!(
+                        "{}, {}: {}",
+                        h.display_date(),
+                        location(h),
+                        holiday_text(h, options, false)
+                    )
+                })
+                .join("\n");
+
+            ureq::json!({
+                "type": "section",
+                "text": {
+                    "type": "plain_text",
+                    "text": lines,
+                    "emoji": false
+                }
+            })
+        }
+        Theme::Default | Theme::Festive => {
+            let holiday_lines = holidays
+                .map(|h| {
+                    let mut elements = vec![ureq::json!({
+                        "type": "text",
+                        "text": format!("{}, ", h.display_date()),
+                    })];
+                    if let Some(emoji) = country_emoji(h, options) {
+                        elements.push(ureq::json!({
+                            "type": "emoji",
+                            "name": emoji,
+                        }));
+                        elements.push(ureq::json!({
+                            "type": "text",
+                            "text": " ",
+                        }));
+                    }
+                    elements.push(ureq::json!({
+                        "type": "text",
+                        "text": format!("{}: ", location(h)),
+                    }));
+
+                    let mut line = rich_text_holiday_line(h, options);
+                    if let Some(rest) = line["elements"].as_array_mut() {
+                        elements.append(rest);
+                    }
+                    line["elements"] = elements.into();
+                    line
+                })
+                .collect_vec();
+
+            ureq::json!({
+                "type": "rich_text",
+                "elements": [
+                    {
+                    "type": "rich_text_list",
+                    "style": "bullet",
+                    "elements": holiday_lines,
+                }]
+            })
+        }
+    }
+}
+
+// For each date, which countries are off (and for what) and which are working as usual.
+fn compare_blocks(
+    holidays: &[Holiday],
+    dates_by_country: &[(String, Vec<NaiveDate>)],
+    options: &MessageOptions,
+) -> Vec<serde_json::Value> {
+    let dates = dates_by_country
+        .iter()
+        .flat_map(|(_, dates)| dates)
+        .unique()
+        .sorted();
+
+    dates
+        .map(|date| {
+            let countries = dates_by_country
+                .iter()
+                .filter(|(_, dates)| dates.contains(date))
+                .map(|(cc, _)| cc.to_uppercase())
+                .unique();
+
+            let (mut off, mut working) = (Vec::new(), Vec::new());
+            for cc in countries {
+                let names = holidays
+                    .iter()
+                    .filter(|h| {
+                        h.date == *date
+                            && h.country
+                                .as_ref()
+                                .is_some_and(|c| c.eq_ignore_ascii_case(&cc))
+                    })
+                    .map(|h| options.holiday_names(h).0)
+                    .unique()
+                    .join(", ");
+
+                if names.is_empty() {
+                    working.push(country_name(&cc, options));
+                } else {
+                    off.push(format!("{} ({})", country_name(&cc, options), names));
+                }
+            }
+
+            let off = if off.is_empty() {
+                "nobody".to_string()
+            } else {
+                off.join(", ")
+            };
+            let working = if working.is_empty() {
+                "nobody".to_string()
+            } else {
+                working.join(", ")
+            };
+
+            match options.theme {
+                Theme::Minimal => ureq::json!({
+                    "type": "section",
+                    "text": {
+                        "type": "plain_text",
+                        "text": format!(
+                            "{}\nOff: {}\nWorking: {}",
+                            date.format(DISPLAY_DATE_FORMAT),
+                            off,
+                            working
+                        ),
+                        "emoji": false
+                    }
+                }),
+                Theme::Default | Theme::Festive => ureq::json!({
+                    "type": "section",
+                    "text": {
+                        "type": "mrkdwn",
+                        "text": format!(
+                            "*{}*\n:palm_tree: Off: {}\n:office: Working: {}",
+                            date.format(DISPLAY_DATE_FORMAT),
+                            escape_mrkdwn(&off),
+                            escape_mrkdwn(&working)
+                        ),
+                    }
+                }),
+            }
+        })
+        .collect()
+}
+
+// Falls back to the code itself for anything that isn't a known ISO 3166-1 code.
+fn country_name(country: &str, options: &MessageOptions) -> String {
+    let code = country.to_uppercase();
+    if let Some(name) = options.country_names.get(iso_country_code(&code)) {
+        return name.clone();
+    }
+
+    CountryCode::for_alpha2_caseless(country)
+        .map(|c| match options.country_display {
+            CountryDisplay::Full => c.name().to_string(),
+            CountryDisplay::Short => c.alpha3().to_string(),
+            CountryDisplay::Code => c.alpha2().to_string(),
+        })
+        .unwrap_or_else(|_| country.to_uppercase())
+}
+
+// The holiday's location, with the country part renamed if there's a --country-name for it or it should
+// be shown as a code. Regional locations look like "Germany - Bavaria", so only the part before the
+// " - " is the country.
+fn display_location(h: &Holiday, options: &MessageOptions) -> Option<String> {
+    let location = h.location.as_ref()?;
+    let name = h.country.as_ref().and_then(|c| {
+        let code = CountryCode::for_alpha2_caseless(c).ok();
+        match (
+            options
+                .country_names
+                .get(iso_country_code(&c.to_uppercase())),
+            options.country_display,
+        ) {
+            (Some(name), _) => Some(name.clone()),
+            (None, CountryDisplay::Full) => None,
+            (None, CountryDisplay::Short) => code.map(|c| c.alpha3().to_string()),
+            (None, CountryDisplay::Code) => code.map(|c| c.alpha2().to_string()),
+        }
+    });
+    let name = name.as_ref();
+
+    match (name, location.split_once(" - ")) {
+        (Some(name), Some((_, region))) => Some(format!("{} - {}", name, region)),
+        (Some(name), None) => Some(name.clone()),
+        (None, _) => Some(location.clone()),
+    }
+}
+
+// Merges holidays with the same name in the same place on consecutive days, e.g. a three-day festival,
+// into a single holiday with a `last_date`.
+fn merge_consecutive_days(holidays: &[Holiday]) -> Vec<Holiday> {
+    let mut merged: Vec<Holiday> = Vec::new();
+
+    for h in holidays.iter().sorted_by_key(|h| h.date) {
+        let previous_day = merged.iter_mut().find(|m| {
+            m.name == h.name
+                && m.country == h.country
+                && m.location == h.location
+                && m.last_date.unwrap_or(m.date).succ_opt() == Some(h.date)
+        });
+
+        match previous_day {
+            Some(m) => m.last_date = Some(h.date),
+            None => merged.push(h.clone()),
+        }
+    }
+
+    merged
+}
+
+// Countries that aren't in any of the configured groups.
+const OTHER_REGION: &str = "Other";
+
+fn region_heading(region: &str, options: &MessageOptions) -> serde_json::Value {
+    match options.theme {
+        Theme::Minimal => ureq::json!({
+            "type": "section",
+            "text": {
+                "type": "plain_text",
+                "text": region.to_uppercase(),
+                "emoji": false
+            }
+        }),
+        Theme::Default | Theme::Festive => ureq::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*{}*", escape_mrkdwn(region)),
+            }
+        }),
+    }
+}
+
+fn location_blocks(
+    location: &str,
+    holidays: &[&Holiday],
+    options: &MessageOptions,
+    show_heading: bool,
+) -> Vec<serde_json::Value> {
+    match (options.style, options.theme) {
+        (Style::Fields, _) => vec![fields_location_section(
+            location,
+            holidays,
+            options,
+            show_heading,
+        )],
+        (Style::List, Theme::Minimal) => vec![plain_location_section(
+            location,
+            holidays,
+            options,
+            show_heading,
+        )],
+        (Style::List, Theme::Default | Theme::Festive) => {
+            let mut blocks = Vec::new();
+
+            if show_heading {
+                blocks.push(ureq::json!(
+                    {
+                        "type": "section",
+                        "text": {
+                            "type": "mrkdwn",
+                            "text": location_heading(location, holidays, options),
+                        }
+                    }
+                ));
+            }
+
+            let holiday_lines: Vec<serde_json::Value> = holidays
+                .iter()
+                .map(|h| rich_text_holiday_line(h, options))
+                .collect();
+
+            blocks.push(ureq::json!(
+                {
+                    "type": "rich_text",
+                    "elements": [
+                        {
+                        "type": "rich_text_list",
+                        "style": "bullet",
+                        "elements": holiday_lines,
+                    }]
+                }
+            ));
+
+            blocks
+        }
+    }
+}
+
+fn rich_text_holiday_line(h: &Holiday, options: &MessageOptions) -> serde_json::Value {
+    let mut elements: Vec<serde_json::Value> = Vec::new();
+
+    let (primary_name, secondary_name) = options.holiday_names(h);
+
+    elements.push(ureq::json!({
+        "type": "text",
+        "text": primary_name,
+        "style": {
+            "bold": true
+        }
+    }));
+
+    if let Some(secondary_name) = secondary_name {
+        elements.push(ureq::json!({
+            "type": "text",
+            "text": format!(" ({})", secondary_name),
+            "style": {
+                "italic": true
+            }
+        }));
+    }
+
+    let holiday_type = h.r#type.as_deref().map(|t| options.type_label(t));
+    if let (true, Some(holiday_type)) = (options.show_types, holiday_type) {
+        elements.push(ureq::json!({
+            "type": "text",
+            "text": " ",
+        }));
+        elements.push(ureq::json!({
+            "type": "text",
+            "text": holiday_type,
+            "style": {
+                "code": true
+            }
+        }));
+    }
+
+    if shows_date(h, options) {
+        elements.push(ureq::json!({
+            "type": "text",
+            "text": format!(" - {}", h.display_date()),
+        }));
+    }
+
+    if let (Theme::Festive, Some(emoji)) = (options.theme, holiday_emoji(&h.name)) {
+        elements.push(ureq::json!({
+            "type": "text",
+            "text": " ",
+        }));
+        elements.push(ureq::json!({
+            "type": "emoji",
+            "name": emoji,
+        }));
+    }
+
+    if options.calendar_links {
+        elements.push(ureq::json!({
+            "type": "text",
+            "text": " ",
+        }));
+        elements.push(ureq::json!({
+            "type": "link",
+            "url": calendar_link(h, options),
+            "text": CALENDAR_LINK_TEXT,
+        }));
+    }
+
+    ureq::json!({
+        "type": "rich_text_section",
+        "elements": elements,
+    })
+}
+
+// Markdown heading for a location's holidays, e.g. "_Australia_".
+fn location_heading(location: &str, holidays: &[&Holiday], options: &MessageOptions) -> String {
+    let location = if is_home(holidays[0], options) {
+        format!(":house: *{}*", escape_mrkdwn(location))
+    } else {
+        format!("_{}_", escape_mrkdwn(location))
+    };
+    let mut heading = match country_emoji(holidays[0], options) {
+        Some(emoji) => format!(":{}: {}", emoji, location),
+        None => location,
+    };
+
+    if let Some(url) = source_link(holidays, options) {
+        heading.push_str(&format!(" <{}|more info>", url));
+    }
+
+    heading
+}
+
+// A location without any markdown, e.g. "Australia", or "Australia (home)" for the home country.
+fn plain_location(location: &str, holidays: &[&Holiday], options: &MessageOptions) -> String {
+    if is_home(holidays[0], options) {
+        format!("{} (home)", location)
+    } else {
+        location.to_string()
+    }
+}
+
+fn is_home(h: &Holiday, options: &MessageOptions) -> bool {
+    match (&options.home_country, &h.country) {
+        (Some(home), Some(country)) => {
+            iso_country_code(home).eq_ignore_ascii_case(iso_country_code(country))
+        }
+        _ => false,
+    }
+}
+
+// Slack treats these as control characters in mrkdwn, see https://api.slack.com/reference/surfaces/formatting#escaping.
+fn escape_mrkdwn(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn source_link(holidays: &[&Holiday], options: &MessageOptions) -> Option<String> {
+    let template = options.link_template.as_ref()?;
+    let country = holidays[0].country.as_ref()?;
+
+    // Sites like Nager.Date only know the ISO code, so UK is linked as GB.
+    Some(template.replace("{country}", iso_country_code(&country.to_uppercase())))
+}
+
+// Whether a holiday's line ends with its date. The flat list starts every line with the date instead.
+fn shows_date(h: &Holiday, options: &MessageOptions) -> bool {
+    !options.flat_chronological && (options.show_dates || h.last_date.is_some())
+}
+
+// A single line of text describing a holiday, with or without markdown styling.
+fn holiday_text(h: &Holiday, options: &MessageOptions, markdown: bool) -> String {
+    let (primary_name, secondary_name) = options.holiday_names(h);
+
+    let mut text = if markdown {
+        format!("*{}*", escape_mrkdwn(primary_name))
+    } else {
+        primary_name.to_string()
+    };
+    if let Some(secondary_name) = secondary_name {
+        if markdown {
+            text.push_str(&format!(" _({})_", escape_mrkdwn(secondary_name)));
+        } else {
+            text.push_str(&format!(" ({})", secondary_name));
+        }
+    }
+    let holiday_type = h.r#type.as_deref().map(|t| options.type_label(t));
+    if let (true, Some(holiday_type)) = (options.show_types, holiday_type) {
+        if markdown {
+            text.push_str(&format!(" `{}`", escape_mrkdwn(holiday_type)));
+        } else {
+            text.push_str(&format!(" [{}]", holiday_type));
+        }
+    }
+    if shows_date(h, options) {
+        text.push_str(&format!(" - {}", h.display_date()));
+    }
+    if let (true, Theme::Festive, Some(emoji)) = (markdown, options.theme, holiday_emoji(&h.name)) {
+        text.push_str(&format!(" :{}:", emoji));
+    }
+    if markdown && options.calendar_links {
+        text.push_str(&format!(
+            " <{}|{}>",
+            calendar_link(h, options),
+            CALENDAR_LINK_TEXT
+        ));
+    }
+
+    text
+}
+
+const CALENDAR_LINK_TEXT: &str = "\u{2795} Add";
+
+// Google Calendar link that opens a new all-day event for the holiday, with its name and dates filled in.
+fn calendar_link(h: &Holiday, options: &MessageOptions) -> String {
+    let (name, _) = options.holiday_names(h);
+    // All-day events end on the day after the last one.
+    let end = h.last_date.unwrap_or(h.date) + chrono::Duration::days(1);
+
+    format!(
+        "https://calendar.google.com/calendar/render?action=TEMPLATE&text={}&dates={}/{}",
+        percent_encode(name),
+        h.date.format("%Y%m%d"),
+        end.format("%Y%m%d")
+    )
+}
+
+// Encodes everything other than unreserved characters (RFC 3986), for use in a URL query.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// A single section with the location and each holiday on its own line, without any styling.
+fn plain_location_section(
+    location: &str,
+    holidays: &[&Holiday],
+    options: &MessageOptions,
+    show_heading: bool,
+) -> serde_json::Value {
+    let mut lines = Vec::new();
+    if show_heading {
+        lines.push(plain_location(location, holidays, options));
+        if let Some(url) = source_link(holidays, options) {
+            lines.push(format!("More info: {}", url));
+        }
+    }
+    lines.extend(
+        holidays
+            .iter()
+            .map(|h| format!("- {}", holiday_text(h, options, false))),
+    );
+
+    ureq::json!({
+        "type": "section",
+        "text": {
+            "type": "plain_text",
+            "text": lines.join("\n"),
+            "emoji": false
+        }
+    })
+}
+
+// A two-column section, with the location on the left and its holidays on the right.
+fn fields_location_section(
+    location: &str,
+    holidays: &[&Holiday],
+    options: &MessageOptions,
+    show_heading: bool,
+) -> serde_json::Value {
+    let mut fields = match options.theme {
+        Theme::Minimal => {
+            let names = holidays
+                .iter()
+                .map(|h| holiday_text(h, options, false))
+                .join("\n");
+
+            vec![
+                ureq::json!({ "type": "plain_text", "text": plain_location(location, holidays, options), "emoji": false }),
+                ureq::json!({ "type": "plain_text", "text": names, "emoji": false }),
+            ]
+        }
+        Theme::Default | Theme::Festive => {
+            let names = holidays
+                .iter()
+                .map(|h| holiday_text(h, options, true))
+                .join("\n");
+
+            vec![
+                ureq::json!({ "type": "mrkdwn", "text": location_heading(location, holidays, options) }),
+                ureq::json!({ "type": "mrkdwn", "text": names }),
+            ]
+        }
+    };
+
+    if !show_heading {
+        fields.remove(0);
+    }
+
+    ureq::json!({
+        "type": "section",
+        "fields": fields,
+    })
+}
+
+fn dry_run_slack(
+    holidays: &[Holiday],
+    options: &MessageOptions,
+    format: ErrorFormat,
+    // Not stdout, which is only for data output like --output jsonl.
+    out: &mut dyn Write,
+) -> Result<()> {
+    let messages = slack_messages(holidays, options);
+    if messages.is_empty() {
+        if matches!(format, ErrorFormat::Text) {
+            writeln!(out, "no holidays, nothing would be sent")?;
+        }
+        return Ok(());
+    }
+
+    let mut problems = Vec::new();
+
+    for (i, message) in messages.iter().enumerate() {
+        let message_problems = validate_slack_payload(message);
+
+        match format {
+            ErrorFormat::Text => {
+                writeln!(out, "{}", serde_json::to_string_pretty(message)?)?;
+                for problem in message_problems.iter() {
+                    if messages.len() > 1 {
+                        writeln!(out, "invalid message {}: {}", i + 1, problem)?;
+                    } else {
+                        writeln!(out, "invalid message: {}", problem)?;
+                    }
+                }
+            }
+            ErrorFormat::Json => writeln!(
+                out,
+                "{}",
+                ureq::json!({
+                    "event": "dry_run",
+                    "message": message,
+                    "problems": message_problems,
+                })
+            )?,
+        }
+
+        problems.extend(message_problems);
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow::format_err!(
+            "message would be rejected by Slack ({} problem(s) found)",
+            problems.len()
+        ));
+    }
+
+    Ok(())
+}
+
+// Renders the message to a standalone HTML page that looks roughly like it would in Slack, so layout
+// changes can be reviewed in a browser. Emoji are left as their ":name:" codes.
+fn write_html_preview(
+    holidays: &[Holiday],
+    options: &MessageOptions,
+    path: &str,
+    format: ErrorFormat,
+) -> Result<()> {
+    let mut body = String::new();
+    for (i, message) in slack_messages(holidays, options).iter().enumerate() {
+        if i > 0 {
+            body.push_str("<hr class=\"message\">\n");
+        }
+
+        match message["blocks"].as_array() {
+            Some(blocks) => {
+                for block in blocks {
+                    body.push_str(&block_html(block));
+                }
+            }
+            None => body.push_str(&format!(
+                "<p>{}</p>\n",
+                mrkdwn_html(message["text"].as_str().unwrap_or_default())
+            )),
+        }
+    }
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Holiday message preview</title>
+<style>
+body {{ font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; font-size: 15px; color: #1d1c1d; max-width: 640px; margin: 2em auto; }}
+h1 {{ font-size: 18px; font-weight: 900; }}
+p, ul {{ margin: 0.5em 0; line-height: 1.45; }}
+code {{ color: #e01e5a; background: #f8f8f8; border: 1px solid #ddd; border-radius: 3px; padding: 0 3px; }}
+a {{ color: #1264a3; text-decoration: none; }}
+.fields {{ display: grid; grid-template-columns: 1fr 1fr; gap: 0.5em 1em; margin: 0.5em 0; }}
+.context {{ font-size: 12px; color: #616061; }}
+</style>
+</head>
+<body>
+{}</body>
+</html>
+"#,
+        body
+    );
+
+    let mut stdout = io::stdout();
+    let mut out = open_output(Some(path), &mut stdout)?;
+    out.write_all(html.as_bytes())
+        .with_context(|| format!("failed to write {}", path))?;
+    if matches!(format, ErrorFormat::Text) {
+        eprintln!("wrote message preview to {}", path);
+    }
+
+    Ok(())
+}
+
+fn block_html(block: &serde_json::Value) -> String {
+    match block["type"].as_str() {
+        Some("header") => format!("<h1>{}</h1>\n", text_object_html(&block["text"])),
+        Some("section") => {
+            let mut html = String::new();
+            if !block["text"].is_null() {
+                html.push_str(&format!("<p>{}</p>\n", text_object_html(&block["text"])));
+            }
+            if let Some(fields) = block["fields"].as_array() {
+                html.push_str("<div class=\"fields\">\n");
+                for field in fields {
+                    html.push_str(&format!("<div>{}</div>\n", text_object_html(field)));
+                }
+                html.push_str("</div>\n");
+            }
+            html
+        }
+        Some("context") => {
+            let elements = block["elements"].as_array().map_or(&[][..], Vec::as_slice);
+            let text = elements.iter().map(text_object_html).join(" ");
+            format!("<p class=\"context\">{}</p>\n", text)
+        }
+        Some("divider") => "<hr>\n".to_string(),
+        Some("rich_text") => {
+            let elements = block["elements"].as_array().map_or(&[][..], Vec::as_slice);
+            elements.iter().map(rich_text_html).collect()
+        }
+        _ => String::new(),
+    }
+}
+
+fn text_object_html(text: &serde_json::Value) -> String {
+    let s = text["text"].as_str().unwrap_or_default();
+    match text["type"].as_str() {
+        Some("mrkdwn") => mrkdwn_html(s),
+        _ => escape_mrkdwn(s).replace('\n', "<br>"),
+    }
+}
+
+fn rich_text_html(element: &serde_json::Value) -> String {
+    let children = || {
+        let elements = element["elements"]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice);
+        elements.iter().map(rich_text_html).collect::<String>()
+    };
+
+    match element["type"].as_str() {
+        Some("rich_text_list") => {
+            let items = element["elements"]
+                .as_array()
+                .map_or(&[][..], Vec::as_slice);
+            let items: String = items
+                .iter()
+                .map(|item| format!("<li>{}</li>\n", rich_text_html(item)))
+                .collect();
+            format!("<ul>\n{}</ul>\n", items)
+        }
+        Some("rich_text_section") => children(),
+        Some("emoji") => format!(":{}:", element["name"].as_str().unwrap_or_default()),
+        Some("link") => {
+            let url = escape_mrkdwn(element["url"].as_str().unwrap_or_default());
+            let text = element["text"].as_str().map_or(url.clone(), escape_mrkdwn);
+            format!("<a href=\"{}\">{}</a>", url.replace('"', "&quot;"), text)
+        }
+        Some("text") => {
+            let mut html = escape_mrkdwn(element["text"].as_str().unwrap_or_default());
+            for (style, tag) in [("code", "code"), ("italic", "i"), ("bold", "b")] {
+                if element["style"][style].as_bool() == Some(true) {
+                    html = format!("<{}>{}</{}>", tag, html, tag);
+                }
+            }
+            html
+        }
+        _ => String::new(),
+    }
+}
+
+// Converts the bits of mrkdwn that we generate (bold, italic, code and links) to HTML. Text is already
+// escaped for mrkdwn, which happens to be the same escaping HTML needs.
+fn mrkdwn_html(text: &str) -> String {
+    let mut html = String::new();
+    let mut open: Vec<char> = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '<' => {
+                let end = rest.find('>').unwrap_or(rest.len());
+                let (url, label) = rest[..end]
+                    .split_once('|')
+                    .unwrap_or((&rest[..end], &rest[..end]));
+                html.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    url.replace('"', "&quot;"),
+                    label
+                ));
+                rest = rest.get(end + 1..).unwrap_or_default();
+            }
+            // Emoji codes like ":palm_tree:" are left as they are, underscores and all.
+            ':' if emoji_code_len(rest).is_some() => {
+                let len = emoji_code_len(rest).unwrap();
+                html.push(':');
+                html.push_str(&rest[..len]);
+                rest = &rest[len..];
+            }
+            '*' | '_' | '`' => {
+                let tag = match c {
+                    '*' => "b",
+                    '_' => "i",
+                    _ => "code",
+                };
+                if open.last() == Some(&c) {
+                    open.pop();
+                    html.push_str(&format!("</{}>", tag));
+                } else {
+                    open.push(c);
+                    html.push_str(&format!("<{}>", tag));
+                }
+            }
+            '\n' => html.push_str("<br>"),
+            c => html.push(c),
+        }
+    }
+
+    // Unmatched markers would otherwise leave the rest of the page styled.
+    while let Some(c) = open.pop() {
+        let tag = match c {
+            '*' => "b",
+            '_' => "i",
+            _ => "code",
+        };
+        html.push_str(&format!("</{}>", tag));
+    }
+
+    html
+}
+
+// Length of an emoji code's name and closing colon at the start of `text`, which follows an opening colon.
+fn emoji_code_len(text: &str) -> Option<usize> {
+    let name_len = text
+        .find(|c: char| !(c.is_ascii_lowercase() || c.is_ascii_digit() || "_+-'".contains(c)))
+        .unwrap_or(text.len());
+
+    (name_len > 0 && text[name_len..].starts_with(':')).then_some(name_len + 1)
+}
+
+// Block Kit limits, from https://api.slack.com/reference/block-kit/blocks.
+const MAX_BLOCKS: usize = 50;
+const MAX_HEADER_TEXT_CHARS: usize = 150;
+const MAX_SECTION_TEXT_CHARS: usize = 3000;
+const MAX_SECTION_FIELDS: usize = 10;
+const MAX_SECTION_FIELD_CHARS: usize = 2000;
+const KNOWN_BLOCK_TYPES: &[&str] = &["header", "section", "rich_text", "context", "divider"];
+
+// Checks the limits our messages could plausibly run into. Slack checks more than this, but these are
+// the ones that depend on the holidays themselves rather than how the message is put together.
+fn validate_slack_payload(message: &serde_json::Value) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let blocks = match message["blocks"].as_array() {
+        Some(blocks) => blocks,
+        None => return problems,
+    };
+
+    if blocks.len() > MAX_BLOCKS {
+        problems.push(format!(
+            "{} blocks, the maximum is {}",
+            blocks.len(),
+            MAX_BLOCKS
+        ));
+    }
+
+    let text_chars = |v: &serde_json::Value| v["text"].as_str().map_or(0, |t| t.chars().count());
+
+    for (i, block) in blocks.iter().enumerate() {
+        match block["type"].as_str() {
+            Some("header") => {
+                let chars = text_chars(&block["text"]);
+                if chars > MAX_HEADER_TEXT_CHARS {
+                    problems.push(format!(
+                        "block {}: header text is {} characters, the maximum is {}",
+                        i, chars, MAX_HEADER_TEXT_CHARS
+                    ));
+                }
+            }
+            Some("section") => {
+                let chars = text_chars(&block["text"]);
+                if chars > MAX_SECTION_TEXT_CHARS {
+                    problems.push(format!(
+                        "block {}: section text is {} characters, the maximum is {}",
+                        i, chars, MAX_SECTION_TEXT_CHARS
+                    ));
+                }
+
+                let fields = block["fields"].as_array().map_or(&[][..], Vec::as_slice);
+                if fields.len() > MAX_SECTION_FIELDS {
+                    problems.push(format!(
+                        "block {}: section has {} fields, the maximum is {}",
+                        i,
+                        fields.len(),
+                        MAX_SECTION_FIELDS
+                    ));
+                }
+                for field in fields {
+                    let chars = text_chars(field);
+                    if chars > MAX_SECTION_FIELD_CHARS {
+                        problems.push(format!(
+                            "block {}: section field is {} characters, the maximum is {}",
+                            i, chars, MAX_SECTION_FIELD_CHARS
+                        ));
+                    }
+                }
+            }
+            Some(t) if KNOWN_BLOCK_TYPES.contains(&t) => {}
+            Some(t) => problems.push(format!("block {}: unknown block type {:?}", i, t)),
+            None => problems.push(format!("block {}: missing block type", i)),
+        }
+    }
+
+    problems
+}
+
+fn send_test_message(
+    webhook_url: &str,
+    timeout: Duration,
+    max_response_bytes: u64,
+    format: ErrorFormat,
+) -> Result<()> {
+    let message = ureq::json!({
+        "text": ":wave: Holiday bot is connected!",
+    });
+
+    post_to_slack(webhook_url, &message, timeout, max_response_bytes, format).map(|_| ())
+}
+
+// Slack responded, but not with success.
+#[derive(Debug)]
+struct SlackStatusError(u16);
+
+impl std::fmt::Display for SlackStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request to Slack API failed (status {})", self.0)
+    }
+}
+
+impl std::error::Error for SlackStatusError {}
+
+fn post_to_slack(
+    webhook_url: &str,
+    message: &serde_json::Value,
+    timeout: Duration,
+    max_response_bytes: u64,
+    format: ErrorFormat,
+) -> Result<u16> {
+    let resp = ureq::post(webhook_url)
+        .timeout(timeout)
+        .send_json(message)
+        .or_any_status()?;
+
+    let status = resp.status();
+    if status >= 400 {
+        let status_text = resp.status_text().to_string();
+        let response = read_response(resp, max_response_bytes)?;
+        let blocks = message["blocks"].as_array();
+        let invalid_blocks = invalid_block_indexes(&response)
+            .into_iter()
+            .filter_map(|index| Some((index, blocks?.get(index)?)))
+            .collect_vec();
+
+        match format {
+            ErrorFormat::Text => {
+                eprintln!("Warning: slack request failed (status {})", status_text);
+                eprintln!("request\n{}\n", serde_json::to_string_pretty(message)?);
+                eprintln!("response\n{}\n", response);
+                for (index, block) in invalid_blocks {
+                    eprintln!(
+                        "invalid block (index {})\n{}\n",
+                        index,
+                        serde_json::to_string_pretty(block)?
+                    );
+                }
+            }
+            ErrorFormat::Json => eprintln!(
+                "{}",
+                ureq::json!({
+                    "event": "slack_error",
+                    "status": status,
+                    "request": message,
+                    "response": response,
+                    "invalid_blocks": invalid_blocks
+                        .iter()
+                        .map(|(index, block)| ureq::json!({ "index": index, "block": block }))
+                        .collect_vec(),
+                })
+            ),
+        }
+
+        return Err(SlackStatusError(status).into());
+    }
+
+    Ok(status)
+}
+
+// Slack points at the offending part of a rejected payload, e.g. "[json-pointer:/blocks/2/text]".
+fn invalid_block_indexes(response: &str) -> Vec<usize> {
+    response
+        .match_indices("/blocks/")
+        .filter_map(|(i, m)| {
+            let digits: String = response[i + m.len()..]
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            digits.parse().ok()
+        })
+        .unique()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Read, Write};
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    // Written to the temp directory, with a name that's unique to the test run.
+    fn temp_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!(
+            "public-holiday-slackbot-{}-{}",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn custom_holidays_use_the_api_format() {
+        let path = temp_file(
+            "custom-holidays.json",
+            r#"[{"name": "Founders' Day", "name_local": "", "country": "AU", "location": "Australia", "date": "03/14/2025"}]"#,
+        );
+
+        let holidays = load_custom_holidays(&path).unwrap();
+        assert_eq!(holidays.len(), 1);
+        assert_eq!(holidays[0].name, "Founders' Day");
+        assert_eq!(holidays[0].date, date("2025-03-14"));
+        // Empty strings are treated as missing, as they are for the API.
+        assert_eq!(holidays[0].name_local, None);
+
+        assert!(load_custom_holidays("/nonexistent/custom-holidays.json").is_err());
+    }
+
+    #[test]
+    fn custom_holidays_are_only_included_for_the_countries_and_dates_fetched() {
+        let custom_holidays = [
+            holiday("Founders' Day", "AU", "Australia", "2025-03-14"),
+            holiday("Founders' Day", "AU", "Australia", "2025-03-15"),
+            holiday("Founders' Day", "DE", "Germany", "2025-03-14"),
+        ];
+        let dates_by_country = [("au".to_string(), vec![date("2025-03-14")])];
+
+        let included =
+            matching_custom_holidays(&custom_holidays, &dates_by_country, &args(&["AU"]));
+        assert_eq!(included.len(), 1);
+        assert_eq!(included[0].country.as_deref(), Some("AU"));
+        assert_eq!(included[0].date, date("2025-03-14"));
+    }
+
+    fn holiday(name: &str, country: &str, location: &str, day: &str) -> Holiday {
+        Holiday {
+            name: name.to_string(),
+            name_local: None,
+            country: Some(country.to_string()),
+            location: Some(location.to_string()),
+            r#type: Some("National".to_string()),
+            language: None,
+            date: date(day),
+            last_date: None,
+        }
+    }
+
+    fn args(argv: &[&str]) -> Args {
+        Args::try_parse_from(["public-holiday-slackbot"].iter().chain(argv)).unwrap()
+    }
+
+    fn options(argv: &[&str]) -> MessageOptions {
+        message_options(&args(argv), &[], None, None)
+    }
+
+    fn timeout() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    // Answers a single request with the given raw HTTP response. Returns the URL to send the request
+    // to, and a handle that gives back the request's body.
+    fn mock_server(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let (url, handle) = mock_server_for(vec![response.to_string()]);
+        let handle = std::thread::spawn(move || handle.join().unwrap().remove(0).1);
+
+        (url, handle)
+    }
+
+    // Like mock_server, but answers one request for each response, in turn. The handle gives back the
+    // request line (e.g. "GET /?country=AU HTTP/1.1") and body of each request.
+    fn mock_server_for(
+        responses: Vec<String>,
+    ) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            responses
+                .iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+
+                    let mut request_line = String::new();
+                    io::BufRead::read_line(&mut reader, &mut request_line).unwrap();
+
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        io::BufRead::read_line(&mut reader, &mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+
+                    stream.write_all(response.as_bytes()).unwrap();
+                    (
+                        request_line.trim().to_string(),
+                        String::from_utf8(body).unwrap(),
+                    )
+                })
+                .collect()
+        });
+
+        (url, handle)
+    }
+
+    // A holidays API response. The connection is closed after each one, so that every request
+    // reaches mock_server_for as a new connection.
+    fn api_response(holidays: &[Holiday]) -> String {
+        let body = serde_json::to_string(holidays).unwrap();
+        format!(
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    // Runs f with SLACK_WEBHOOK_URL set to url. There's only one environment for every test, so tests
+    // that post to Slack take turns.
+    fn with_slack_webhook_url<T>(url: &str, f: impl FnOnce() -> T) -> T {
+        static SLACK_WEBHOOK_URL_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let _lock = SLACK_WEBHOOK_URL_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        env::set_var("SLACK_WEBHOOK_URL", url);
+        let result = f();
+        env::remove_var("SLACK_WEBHOOK_URL");
+        result
+    }
+
+    fn read_jsonl(path: &str) -> Vec<Holiday> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn always_show_date_adds_the_date_to_each_line() {
+        let h = holiday("Christmas Day", "AU", "Australia", "2025-12-25");
+
+        assert_eq!(holiday_text(&h, &options(&["AU"]), false), "Christmas Day");
+        assert_eq!(
+            holiday_text(&h, &options(&["AU", "--always-show-date"]), false),
+            "Christmas Day - Thu 25 Dec 2025"
+        );
+    }
+
+    #[test]
+    fn types_for_parses_a_country_and_its_types() {
+        let (country, types) = parse_country_types("de:National,Local holiday").unwrap();
+        assert_eq!(country, "DE");
+        assert_eq!(types, ["National", "Local holiday"]);
+
+        let (country, _) = parse_country_types("Germany:National").unwrap();
+        assert_eq!(country, "DE");
+
+        assert!(parse_country_types("DE=National").is_err());
+    }
+
+    #[test]
+    fn types_for_changes_the_types_included_for_one_country() {
+        let boxing_day = |country, location| Holiday {
+            r#type: Some("Observance".to_string()),
+            ..holiday("Boxing Day", country, location, "2025-12-26")
+        };
+        let (url, requests) = mock_server_for(vec![
+            api_response(&[
+                holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+                boxing_day("AU", "Australia"),
+            ]),
+            api_response(&[
+                holiday("Christmas Day", "GB", "United Kingdom", "2025-12-25"),
+                boxing_day("GB", "United Kingdom"),
+            ]),
+        ]);
+        let out = temp_file("types-for.jsonl", "");
+
+        let args = args(&[
+            "AU,UK",
+            "--types-for",
+            "gb:Observance",
+            "--output",
+            "jsonl",
+            "--out",
+            &out,
+            "--holidays-api-url",
+            &url,
+        ]);
+        run(&args, "key", date("2025-12-25")).unwrap();
+        requests.join().unwrap();
+
+        let found = read_jsonl(&out)
+            .into_iter()
+            .map(|h| (h.country.unwrap(), h.name))
+            .collect_vec();
+        assert_eq!(
+            found,
+            [
+                ("AU".to_string(), "Christmas Day".to_string()),
+                ("GB".to_string(), "Boxing Day".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn request_delay_waits_between_countries() {
+        let christmas = |country, location| {
+            api_response(&[holiday("Christmas Day", country, location, "2025-12-25")])
+        };
+        let (url, requests) = mock_server_for(vec![
+            christmas("AU", "Australia"),
+            christmas("NZ", "New Zealand"),
+        ]);
+        let out = temp_file("request-delay.jsonl", "");
+
+        let args = args(&[
+            "AU,NZ",
+            "--request-delay-ms",
+            "500",
+            "--output",
+            "jsonl",
+            "--out",
+            &out,
+            "--holidays-api-url",
+            &url,
+        ]);
+        let started = Instant::now();
+        run(&args, "key", date("2025-12-25")).unwrap();
+        requests.join().unwrap();
+
+        // Each fetch already waits a second for the API's rate limit, and the delay is on top of that.
+        assert!(started.elapsed() >= Duration::from_millis(2 * 1000 + 500));
+        assert_eq!(read_jsonl(&out).len(), 2);
+    }
+
+    #[test]
+    fn limit_countries_only_fetches_the_first_countries() {
+        let christmas = |country, location| {
+            api_response(&[holiday("Christmas Day", country, location, "2025-12-25")])
+        };
+        let (url, requests) = mock_server_for(vec![
+            christmas("AU", "Australia"),
+            christmas("NZ", "New Zealand"),
+        ]);
+        let out = temp_file("limit-countries.jsonl", "");
+
+        // Fetching GB as well would fail once the mock server has stopped, and fail the run.
+        let args = args(&[
+            "AU,NZ,GB",
+            "--limit-countries",
+            "2",
+            "--warn-threshold",
+            "0",
+            "--fail-over-threshold",
+            "--output",
+            "jsonl",
+            "--out",
+            &out,
+            "--holidays-api-url",
+            &url,
+        ]);
+        run(&args, "key", date("2025-12-25")).unwrap();
+
+        let requested = requests
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|(request_line, _)| request_line)
+            .collect_vec();
+        assert!(requested[0].contains("country=AU"), "{}", requested[0]);
+        assert!(requested[1].contains("country=NZ"), "{}", requested[1]);
+        assert_eq!(read_jsonl(&out).len(), 2);
+    }
+
+    #[test]
+    fn interval_runs_until_shut_down() {
+        let args = args(&[
+            "AU",
+            "--interval-secs",
+            "1",
+            "--now",
+            "2025-12-25T09:00:00+11:00",
+        ]);
+        let shutdown = AtomicBool::new(false);
+        let mut dates = Vec::new();
+
+        let started = Instant::now();
+        run_on_interval(&args, Duration::from_millis(10), &shutdown, |date| {
+            dates.push(date);
+            if dates.len() == 3 {
+                shutdown.store(true, Ordering::SeqCst);
+            }
+        });
+
+        assert_eq!(dates, [date("2025-12-25"); 3]);
+        // Two waits between the three runs, and none after the last.
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn out_needs_an_output_that_writes_to_it() {
+        let check = |argv: &[&str]| check_out(&args(argv)).map_err(|e| e.kind());
+        let conflict = Err(clap::error::ErrorKind::ArgumentConflict);
+
+        assert_eq!(
+            check(&["AU", "--output", "jsonl", "--out", "a.jsonl"]),
+            Ok(())
+        );
+        assert_eq!(
+            check(&["AU", "--output", "slack,slack-json", "--out", "-"]),
+            Ok(())
+        );
+        assert_eq!(check(&["AU", "--out", "a.jsonl"]), conflict);
+        assert_eq!(
+            check(&["AU", "--output", "jsonl,slack-json", "--out", "-"]),
+            Ok(())
+        );
+        assert_eq!(
+            check(&["AU", "--output", "jsonl,slack-json", "--out", "a.jsonl"]),
+            conflict
+        );
+    }
+
+    #[test]
+    fn summary_counts_the_countries_that_failed() {
+        let (url, requests) = mock_server_for(vec![
+            api_response(&[holiday("Christmas Day", "AU", "Australia", "2025-12-25")]),
+            "HTTP/1.1 500 Internal Server Error\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+                .to_string(),
+        ]);
+        let out = temp_file("summary.jsonl", "");
+        let args = args(&[
+            "AU,NZ",
+            "--output",
+            "jsonl",
+            "--out",
+            &out,
+            "--holidays-api-url",
+            &url,
+        ]);
+        let dates_by_country = [
+            ("AU".to_string(), vec![date("2025-12-25")]),
+            ("NZ".to_string(), vec![date("2025-12-25")]),
+        ];
+
+        let outcome =
+            fetch_and_send(&args, "key", date("2025-12-25"), &dates_by_country, &mut 0).unwrap();
+        requests.join().unwrap();
+
+        assert!(outcome.result.is_ok());
+        assert_eq!(
+            (
+                outcome.countries,
+                outcome.failed_countries,
+                outcome.failed_fetches
+            ),
+            (2, 1, 1)
+        );
+        assert!(
+            summary_text(&outcome).starts_with(
+                "summary: fetched 2 countries (1 succeeded, 1 failed), found 1 holidays, took "
+            ),
+            "{}",
+            summary_text(&outcome)
+        );
+    }
+
+    #[test]
+    fn result_json_describes_what_was_sent() {
+        let christmas =
+            || api_response(&[holiday("Christmas Day", "AU", "Australia", "2025-12-25")]);
+        let (url, requests) = mock_server_for(vec![christmas(), christmas()]);
+        let args = args(&["AU", "--result-json", "--holidays-api-url", &url]);
+        let dates_by_country = [("AU".to_string(), vec![date("2025-12-25")])];
+        let outcome = |slack_response| {
+            let (slack_url, _) = mock_server(slack_response);
+            with_slack_webhook_url(&slack_url, || {
+                fetch_and_send(&args, "key", date("2025-12-25"), &dates_by_country, &mut 0)
+            })
+            .unwrap()
+        };
+
+        let sent = outcome("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        assert_eq!(
+            result_json(&args, &sent),
+            serde_json::json!({
+                "success": true,
+                "outputs": ["slack"],
+                "holidays": 1,
+                "messages_sent": 1,
+                "http_status": 200,
+                "error": null,
+            })
+        );
+
+        let rejected = outcome("HTTP/1.1 404 Not Found\r\nContent-Length: 10\r\n\r\nno_service");
+        requests.join().unwrap();
+        let json = result_json(&args, &rejected);
+        assert_eq!(json["success"], false);
+        assert_eq!(json["messages_sent"], 0);
+        assert_eq!(json["http_status"], 404);
+        assert!(json["error"].as_str().unwrap().contains("404"), "{}", json);
+    }
+
+    #[test]
+    fn test_webhook_posts_a_hello_message() {
+        let (url, request) = mock_server("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        send_test_message(&url, timeout(), 1024, ErrorFormat::Text).unwrap();
+        assert!(request.join().unwrap().contains("Holiday bot is connected"));
+
+        let (url, _) =
+            mock_server("HTTP/1.1 404 Not Found\r\nContent-Length: 10\r\n\r\nno_service");
+        assert!(send_test_message(&url, timeout(), 1024, ErrorFormat::Text).is_err());
+    }
+
+    #[test]
+    fn name_style_skips_local_names_that_match_the_english_one() {
+        let mut h = holiday("Christmas Day", "DE", "Germany", "2025-12-25");
+        h.name_local = Some("Weihnachten".to_string());
+        let mut same = holiday("Boxing Day", "AU", "Australia", "2025-12-26");
+        same.name_local = Some("Boxing Day".to_string());
+
+        assert_eq!(
+            h.display_names(NameStyle::Auto, None),
+            ("Christmas Day", Some("Weihnachten"))
+        );
+        assert_eq!(
+            same.display_names(NameStyle::Auto, None),
+            ("Boxing Day", None)
+        );
+        assert_eq!(
+            same.display_names(NameStyle::Both, None),
+            ("Boxing Day", Some("Boxing Day"))
+        );
+        assert_eq!(
+            h.display_names(NameStyle::Local, None),
+            ("Weihnachten", None)
+        );
+        assert_eq!(
+            h.display_names(NameStyle::English, None),
+            ("Christmas Day", None)
+        );
+    }
+
+    #[test]
+    fn jsonl_writes_one_holiday_per_line() {
+        let path = temp_file("holidays.jsonl", "");
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Boxing Day", "AU", "Australia", "2025-12-26"),
+        ];
+        write_jsonl(&holidays, Some(&path), &mut io::stdout()).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect_vec();
+        assert_eq!(lines.len(), 2);
+
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["name"], "Christmas Day");
+        // The same date format as the API, so the output can be used as --custom-holidays.
+        assert_eq!(first["date"], "12/25/2025");
+        let second: Holiday = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second.date, date("2025-12-26"));
+    }
+
+    #[test]
+    fn only_local_holidays_are_regional() {
+        let mut h = holiday(
+            "Saint Stephen's Day",
+            "ES",
+            "Spain - Catalonia",
+            "2025-12-26",
+        );
+        h.r#type = Some("Local holiday".to_string());
+        assert!(h.is_regional());
+
+        h.r#type = Some("National".to_string());
+        assert!(!h.is_regional());
+        h.r#type = None;
+        assert!(!h.is_regional());
+    }
+
+    #[test]
+    fn skip_dates_leave_out_holidays_on_those_dates() {
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+            holiday("Boxing Day", "AU", "Australia", "2025-12-26"),
+            holiday("St Stephen's Day", "DE", "Germany", "2025-12-26"),
+            holiday("New Year's Day", "AU", "Australia", "2026-01-01"),
+        ];
+
+        let args = args(&["AU,DE", "--skip-dates", "2025-12-25,2025-12-26"]);
+        let kept = filter_holidays(&holidays, None, &args);
+        assert_eq!(
+            kept.iter().map(|h| h.name.as_str()).collect_vec(),
+            ["New Year's Day"]
+        );
+
+        assert!(Args::try_parse_from([
+            "public-holiday-slackbot",
+            "AU",
+            "--skip-dates",
+            "25/12/2025"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn themes_change_the_header_and_emoji() {
+        assert_eq!(Theme::Default.header_text(None), ":calendar: Holidays");
+        assert_eq!(
+            Theme::Minimal.header_text(Some("in December")),
+            "Holidays in December"
+        );
+        assert_eq!(Theme::Festive.header_text(None), ":tada: Holidays :tada:");
+
+        let h = holiday("Christmas Day", "AU", "Australia", "2025-12-25");
+        assert_eq!(country_emoji(&h, &options(&["AU"])), None);
+        assert_eq!(
+            country_emoji(&h, &options(&["AU", "--theme", "festive"])),
+            Some("flag-au".to_string())
+        );
+        assert_eq!(holiday_emoji("Christmas Day"), Some("christmas_tree"));
+        assert_eq!(holiday_emoji("Australia Day"), None);
+    }
+
+    #[test]
+    fn holiday_types_match_ignoring_case_and_whitespace() {
+        assert!(holiday_type_in("National", ALLOWED_HOLIDAY_TYPES));
+        assert!(holiday_type_in(" local HOLIDAY ", ALLOWED_HOLIDAY_TYPES));
+        assert!(holiday_type_in("observance", &["Observance "]));
+        assert!(!holiday_type_in("Observance", ALLOWED_HOLIDAY_TYPES));
+    }
+
+    #[test]
+    fn slack_posts_give_up_after_the_timeout() {
+        // Connections are queued but never answered.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let started = std::time::Instant::now();
+        let result = send_test_message(&url, Duration::from_millis(200), 1024, ErrorFormat::Text);
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let args = args(&["AU", "--fetch-timeout-secs", "5"]);
+        assert_eq!(args.fetch_timeout_secs, 5);
+        assert_eq!(args.slack_timeout_secs, 10);
+    }
+
+    #[test]
+    fn this_month_covers_every_day_of_the_month() {
+        let days = days_in_month(date("2024-02-14"));
+        assert_eq!(days.len(), 29);
+        assert_eq!(days.first(), Some(&date("2024-02-01")));
+        assert_eq!(days.last(), Some(&date("2024-02-29")));
+
+        assert_eq!(days_in_month(date("2025-12-31")).len(), 31);
+    }
+
+    #[test]
+    fn fetch_errors_have_a_kind_for_structured_output() {
+        let parse_error = HolidayError::Parse(io::Error::new(io::ErrorKind::InvalidData, "bad"));
+        assert_eq!(error_kind(&parse_error), "parse");
+        assert_eq!(error_kind(&HolidayError::Status(500)), "http_status");
+        assert_eq!(error_kind(&HolidayError::Timeout), "timeout");
+    }
+
+    #[test]
+    fn fields_style_puts_the_location_next_to_its_holidays() {
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+        ];
+        let message = build_slack_payload(&holidays, &options(&["AU,DE", "--style", "fields"]));
+
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[1]["type"], "section");
+        assert_eq!(blocks[1]["fields"][0]["text"], "_Australia_");
+        assert_eq!(blocks[1]["fields"][1]["text"], "*Christmas Day*");
+        assert_eq!(blocks[2]["fields"][0]["text"], "_Germany_");
+    }
+
+    #[test]
+    fn link_source_links_each_country() {
+        let h = holiday("Christmas Day", "au", "Australia", "2025-12-25");
+
+        assert_eq!(source_link(&[&h], &options(&["AU"])), None);
+        assert_eq!(
+            source_link(&[&h], &options(&["AU", "--link-source"])),
+            Some("https://date.nager.at/PublicHoliday/Country/AU".to_string())
+        );
+        assert_eq!(
+            source_link(
+                &[&h],
+                &options(&["AU", "--link-source=https://example.com/{country}"])
+            ),
+            Some("https://example.com/AU".to_string())
+        );
+        assert_eq!(
+            location_heading(
+                "Australia",
+                &[&h],
+                &options(&["AU", "--link-source=https://example.com/{country}"])
+            ),
+            "_Australia_ <https://example.com/AU|more info>"
+        );
+
+        let h = holiday("Christmas Day", "UK", "United Kingdom", "2025-12-25");
+        assert_eq!(
+            source_link(&[&h], &options(&["UK", "--link-source"])),
+            Some("https://date.nager.at/PublicHoliday/Country/GB".to_string())
+        );
+    }
+
+    #[test]
+    fn countries_can_be_given_by_name() {
+        assert_eq!(resolve_country_code("AU").unwrap(), "AU");
+        // Codes are passed through, as the API accepts some that aren't ISO codes.
+        assert_eq!(resolve_country_code("uk").unwrap(), "uk");
+        assert_eq!(resolve_country_code("Australia").unwrap(), "AU");
+        assert_eq!(resolve_country_code(" united states ").unwrap(), "US");
+        assert_eq!(resolve_country_code("Great Britain").unwrap(), "GB");
+        assert_eq!(resolve_country_code("Switz").unwrap(), "CH");
+
+        assert!(resolve_country_code("Atlantis").is_err());
+        let ambiguous = resolve_country_code("Mal").unwrap_err().to_string();
+        assert!(ambiguous.starts_with("ambiguous country"));
+    }
+
+    #[test]
+    fn name_lang_prefers_names_in_that_language() {
+        let mut h = holiday("Christmas Day", "DE", "Germany", "2025-12-25");
+        h.name_local = Some("Weihnachten".to_string());
+        h.language = Some("de".to_string());
+        let names = |options: &MessageOptions| {
+            h.display_names(options.name_style, options.name_language.as_deref())
+        };
+
+        assert_eq!(
+            names(&options(&["DE", "--name-lang", "DE"])),
+            ("Weihnachten", None)
+        );
+        assert_eq!(
+            names(&options(&["DE", "--name-lang", "en"])),
+            ("Christmas Day", None)
+        );
+        // Without a name in that language, --name-style applies as usual.
+        assert_eq!(
+            names(&options(&["DE", "--name-lang", "fr"])),
+            ("Christmas Day", Some("Weihnachten"))
+        );
+    }
+
+    #[test]
+    fn collapse_single_country_drops_the_only_heading() {
+        let holidays = [holiday("Christmas Day", "AU", "Australia", "2025-12-25")];
+        let has_heading = |message: &serde_json::Value| message.to_string().contains("_Australia_");
+
+        assert!(has_heading(&build_slack_payload(
+            &holidays,
+            &options(&["AU"])
+        )));
+        assert!(!has_heading(&build_slack_payload(
+            &holidays,
+            &options(&["AU", "--collapse-single-country"])
+        )));
+
+        // Headings stay when there's more than one place.
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+        ];
+        assert!(has_heading(&build_slack_payload(
+            &holidays,
+            &options(&["AU,DE", "--collapse-single-country"])
+        )));
+    }
+
+    #[test]
+    fn this_weekend_is_the_weekend_on_or_after_the_date() {
+        let weekend = [Weekday::Sat, Weekday::Sun];
+
+        assert_eq!(
+            upcoming_weekend(date("2025-12-24"), weekend),
+            [date("2025-12-27"), date("2025-12-28")]
+        );
+        assert_eq!(
+            upcoming_weekend(date("2025-12-27"), weekend),
+            [date("2025-12-27"), date("2025-12-28")]
+        );
+    }
+
+    #[test]
+    fn rejected_blocks_are_found_in_slack_errors() {
+        let response = r#"{"ok":false,"error":"invalid_blocks","errors":["must be less than 151 characters [json-pointer:/blocks/0/text/text]","invalid [json-pointer:/blocks/12/fields]","again [json-pointer:/blocks/0/text]"]}"#;
+        assert_eq!(invalid_block_indexes(response), [0, 12]);
+
+        assert!(invalid_block_indexes("invalid_payload").is_empty());
+    }
+
+    #[test]
+    fn idempotency_file_skips_messages_that_were_already_sent() {
+        // Hashes are saved between runs, so they mustn't change.
+        assert_eq!(
+            payload_hash(&ureq::json!({ "text": "hi" })),
+            "8d0a57882bd22a7d"
+        );
+
+        let path = temp_file("idempotency", "");
+        fs::remove_file(&path).unwrap();
+        let args = args(&["AU", "--idempotency-file", &path]);
+        let options = message_options(&args, &[], None, None);
+        let holidays = [holiday("Christmas Day", "AU", "Australia", "2025-12-25")];
+        let mut statuses = Vec::new();
+
+        let (url, _) = mock_server("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        send_to_slack(&url, &holidays, &options, &args, &mut statuses).unwrap();
+        assert_eq!(statuses, [200]);
+
+        // Nothing is listening, so this would fail if it posted again.
+        send_to_slack(
+            "http://127.0.0.1:9/",
+            &holidays,
+            &options,
+            &args,
+            &mut statuses,
+        )
+        .unwrap();
+        assert_eq!(statuses, [200]);
+    }
+
+    #[test]
+    fn weekends_differ_between_countries() {
+        assert_eq!(weekend_for("AU"), [Weekday::Sat, Weekday::Sun]);
+        assert_eq!(weekend_for("sa"), [Weekday::Fri, Weekday::Sat]);
+        assert_eq!(weekend_for("UK"), [Weekday::Sat, Weekday::Sun]);
+
+        assert_eq!(
+            upcoming_weekend(date("2025-12-24"), weekend_for("AE")),
+            [date("2025-12-27"), date("2025-12-28")]
+        );
+        assert_eq!(
+            upcoming_weekend(date("2025-12-24"), weekend_for("IL")),
+            [date("2025-12-26"), date("2025-12-27")]
+        );
+    }
+
+    #[test]
+    fn out_creates_missing_directories() {
+        let dir = env::temp_dir().join(format!(
+            "public-holiday-slackbot-{}-out",
+            std::process::id()
+        ));
+        let path = dir.join("nested").join("holidays.jsonl");
+        let h = holiday("Christmas Day", "AU", "Australia", "2025-12-25");
+
+        write_jsonl(&[h], path.to_str(), &mut io::stdout()).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count(), 1);
+        assert!(written.contains(r#""name":"Christmas Day""#));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dry_run_leaves_stdout_to_the_data_output() {
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Boxing Day", "AU", "Australia", "2025-12-26"),
+        ];
+        let args = args(&["AU", "--dry-run", "--output", "slack,jsonl"]);
+        let options = message_options(&args, &[], None, None);
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        for output in args.output.iter() {
+            send_output(
+                *output,
+                &holidays,
+                &options,
+                &args,
+                &mut stdout,
+                &mut stderr,
+                &mut Vec::new(),
+            )
+            .unwrap();
+        }
+
+        let written = String::from_utf8(stdout)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Holiday>(line).unwrap().name)
+            .collect_vec();
+        assert_eq!(written, ["Christmas Day", "Boxing Day"]);
+
+        let payload: serde_json::Value = serde_json::from_slice(&stderr).unwrap();
+        assert_eq!(payload, slack_messages(&holidays, &options)[0]);
+    }
+
+    #[test]
+    fn dry_run_reports_payloads_slack_would_reject() {
+        let header = "x".repeat(MAX_HEADER_TEXT_CHARS + 1);
+        let message = serde_json::json!({
+            "blocks": [
+                {"type": "header", "text": {"type": "plain_text", "text": header}},
+                {"type": "section", "text": {"type": "mrkdwn", "text": "fine"}},
+                {"type": "table"},
+                {},
+            ]
+        });
+
+        assert_eq!(
+            validate_slack_payload(&message),
+            [
+                "block 0: header text is 151 characters, the maximum is 150",
+                "block 2: unknown block type \"table\"",
+                "block 3: missing block type",
+            ]
+        );
+
+        let h = holiday("Christmas Day", "AU", "Australia", "2025-12-25");
+        for message in slack_messages(&[h], &options(&["AU"])) {
+            assert_eq!(validate_slack_payload(&message), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn a_country_can_have_its_own_date() {
+        let request = parse_country_request("US@2025-07-04").unwrap();
+        assert_eq!(request.country, "US");
+        assert_eq!(request.date, Some(date("2025-07-04")));
+
+        let request = parse_country_request("Australia").unwrap();
+        assert_eq!(request.country, "AU");
+        assert_eq!(request.date, None);
+
+        assert!(parse_country_request("US@2025-13-01").is_err());
+        assert!(parse_country_request("US@").is_err());
+
+        // The same goes for the date for every country.
+        assert_eq!(
+            args(&["AU", "--date", "2025-07-04"]).date,
+            Some(date("2025-07-04"))
+        );
+        assert!(
+            Args::try_parse_from(["public-holiday-slackbot", "AU", "--date", "2025-13-01"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn show_types_tags_each_holiday() {
+        let h = holiday("Christmas Day", "AU", "Australia", "2025-12-25");
+        let options = options(&["AU", "--show-types"]);
+
+        assert_eq!(
+            holiday_text(&h, &options, true),
+            "*Christmas Day* `National`"
+        );
+        assert_eq!(
+            holiday_text(&h, &options, false),
+            "Christmas Day [National]"
+        );
+    }
+
+    #[test]
+    fn country_groups_put_countries_under_region_headings() {
+        let (region, countries) = parse_country_group("APAC=AU,New Zealand").unwrap();
+        assert_eq!(region, "APAC");
+        assert_eq!(countries, ["AU", "NZ"]);
+        assert!(parse_country_group("AU,NZ").is_err());
+
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "US", "United States", "2025-12-25"),
+        ];
+        let message = build_slack_payload(
+            &holidays,
+            &options(&["AU", "--country-group", "APAC=AU,NZ"]),
+        );
+        let texts = message["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|b| b["text"]["text"].as_str())
+            .collect_vec();
+
+        assert_eq!(
+            texts[1..],
+            ["*APAC*", "_Australia_", "*Other*", "_United States_"]
+        );
+    }
+
+    #[test]
+    fn terse_posts_a_single_line() {
+        let holidays = [holiday("Christmas Day", "AU", "Australia", "2025-12-25")];
+
+        let message = build_slack_payload(&holidays, &options(&["AU", "--terse"]));
+        assert_eq!(message["blocks"].as_array().unwrap().len(), 1);
+        assert_eq!(message["blocks"][0]["text"]["text"], DEFAULT_TERSE_TEXT);
+
+        let message = build_slack_payload(&holidays, &options(&["AU", "--terse=Day off!"]));
+        assert_eq!(message["blocks"][0]["text"]["text"], "Day off!");
+    }
+
+    #[test]
+    fn holiday_data_is_escaped_in_mrkdwn() {
+        assert_eq!(escape_mrkdwn("Q&A <b>"), "Q&amp;A &lt;b&gt;");
+
+        let h = holiday("Saints Peter & Paul", "PE", "Peru <Lima>", "2025-06-29");
+        let options = options(&["PE"]);
+        assert_eq!(
+            holiday_text(&h, &options, true),
+            "*Saints Peter &amp; Paul*"
+        );
+        assert_eq!(holiday_text(&h, &options, false), "Saints Peter & Paul");
+        assert_eq!(
+            location_heading("Peru <Lima>", &[&h], &options),
+            "_Peru &lt;Lima&gt;_"
+        );
+    }
+
+    #[test]
+    fn validate_config_lists_every_problem() {
+        let problems = validate_config(&args(&[
+            "AU,UK",
+            "XX",
+            "--types-for",
+            "QQ:National,",
+            "--types-for",
+            "uk:National",
+            "--names-file",
+            "/nonexistent/names.json",
+        ]));
+
+        for problem in [
+            "unknown country code \"XX\"",
+            "--types-for: unknown country code \"QQ\"",
+            "--types-for: empty holiday type for QQ",
+        ] {
+            assert!(problems.iter().any(|p| p == problem), "{:?}", problems);
+        }
+        assert!(
+            problems.iter().any(|p| p.starts_with("--names-file: ")),
+            "{:?}",
+            problems
+        );
+        assert!(
+            !problems
+                .iter()
+                .any(|p| p.contains("\"AU\"") || p.to_uppercase().contains("\"UK\"")),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn group_by_continent_uses_continent_headings() {
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "UK", "United Kingdom", "2025-12-25"),
+            holiday("Christmas Day", "US", "United States", "2025-12-25"),
+        ];
+        let message = build_slack_payload(&holidays, &options(&["AU", "--group-by", "continent"]));
+        let texts = message["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|b| b["text"]["text"].as_str())
+            .collect_vec();
+
+        assert_eq!(
+            texts[1..],
+            [
+                "*Europe*",
+                "_United Kingdom_",
+                "*North America*",
+                "_United States_",
+                "*Oceania*",
+                "_Australia_",
+            ]
+        );
+    }
+
+    #[test]
+    fn mrkdwn_converts_to_html() {
+        assert_eq!(
+            mrkdwn_html("*Christmas Day* _(Weihnachten)_ `National`\n:palm_tree: <https://example.com/?q=\"x\"|more info>"),
+            "<b>Christmas Day</b> <i>(Weihnachten)</i> <code>National</code><br>:palm_tree: <a href=\"https://example.com/?q=&quot;x&quot;\">more info</a>"
+        );
+        assert_eq!(
+            mrkdwn_html("<https://example.com>"),
+            "<a href=\"https://example.com\">https://example.com</a>"
+        );
+        assert_eq!(
+            mrkdwn_html("*unclosed _tags"),
+            "<b>unclosed <i>tags</i></b>"
+        );
+    }
+
+    #[test]
+    fn flat_chronological_lists_holidays_in_date_order() {
+        let holidays = [
+            holiday("Boxing Day", "AU", "Australia", "2025-12-26"),
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+        ];
+        let options = options(&["AU", "--flat-chronological", "--theme", "minimal"]);
+
+        assert_eq!(
+            flat_chronological_block(&holidays, &options)["text"]["text"],
+            "Thu 25 Dec 2025, Germany: Christmas Day\nFri 26 Dec 2025, Australia: Boxing Day"
+        );
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        assert!(is_retryable(&HolidayError::RateLimited {
+            retry_after: None
+        }));
+        assert!(is_retryable(&HolidayError::Timeout));
+        assert!(is_retryable(&HolidayError::Status(503)));
+
+        assert!(!is_retryable(&HolidayError::Status(401)));
+        assert!(!is_retryable(&HolidayError::NotFound));
+        assert!(!is_retryable(&HolidayError::Parse(io::Error::other(
+            "bad json"
+        ))));
+    }
+
+    #[test]
+    fn retries_stop_when_the_budget_runs_out() {
+        let args = args(&["AU,NZ", "--retry-budget", "2"]);
+        let mut retries_left = args.retry_budget;
+        let attempts = std::cell::Cell::new(0);
+        let fetch = |e: fn() -> HolidayError| {
+            let attempts = &attempts;
+            move || {
+                attempts.set(attempts.get() + 1);
+                Err::<Vec<Holiday>, _>(e())
+            }
+        };
+        let rate_limited = || HolidayError::RateLimited {
+            retry_after: Some(Duration::ZERO),
+        };
+
+        // Errors that won't go away aren't retried, and don't use up the budget.
+        let result = fetch_with_retries(
+            &args,
+            "AU",
+            date("2025-12-25"),
+            &mut retries_left,
+            fetch(|| HolidayError::NotFound),
+        );
+        assert!(matches!(result, Err(HolidayError::NotFound)));
+        assert_eq!((attempts.replace(0), retries_left), (1, 2));
+
+        let result = fetch_with_retries(
+            &args,
+            "AU",
+            date("2025-12-26"),
+            &mut retries_left,
+            fetch(rate_limited),
+        );
+        assert!(result.is_err());
+        assert_eq!((attempts.replace(0), retries_left), (3, 0));
+
+        // The budget is shared, so there's none left for the next country.
+        let result = fetch_with_retries(
+            &args,
+            "NZ",
+            date("2025-12-25"),
+            &mut retries_left,
+            fetch(rate_limited),
+        );
+        assert!(result.is_err());
+        assert_eq!((attempts.get(), retries_left), (1, 0));
+    }
+
+    #[test]
+    fn explain_says_why_a_holiday_of_another_type_was_dropped() {
+        let h = Holiday {
+            r#type: Some("Observance".to_string()),
+            ..holiday("Boxing Day", "AU", "Australia", "2025-12-26")
+        };
+
+        let (kept, reason) = type_filter(&h, ALLOWED_HOLIDAY_TYPES);
+        assert!(!kept);
+        assert_eq!(
+            explanation(ErrorFormat::Text, &h, kept, &reason),
+            "explain: Boxing Day on 2025-12-26 for AU: dropped (type \"Observance\" isn't one of: National, Local holiday)"
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&explanation(
+                ErrorFormat::Json,
+                &h,
+                kept,
+                &reason
+            ))
+            .unwrap(),
+            serde_json::json!({
+                "event": "explain",
+                "country": "AU",
+                "date": "2025-12-26",
+                "name": "Boxing Day",
+                "kept": false,
+                "reason": "type \"Observance\" isn't one of: National, Local holiday",
+            })
+        );
+
+        let (kept, reason) = type_filter(
+            &holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            ALLOWED_HOLIDAY_TYPES,
+        );
+        assert!(kept, "{}", reason);
+    }
+
+    #[test]
+    fn slack_json_writes_each_message() {
+        let path = temp_file("slack.json", "");
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+        ];
+        let options = options(&["AU", "--message-per-country"]);
+
+        write_slack_json(
+            &holidays,
+            &options,
+            Some(&path),
+            ErrorFormat::Text,
+            &mut io::stdout(),
+        )
+        .unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let messages = serde_json::Deserializer::from_str(&written)
+            .into_iter::<serde_json::Value>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(messages, slack_messages(&holidays, &options));
+    }
+
+    #[test]
+    fn required_env_vars_depend_on_what_is_done() {
+        let names = |argv: &[&str]| {
+            required_env_vars(&args(argv))
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect_vec()
+        };
+
+        assert_eq!(names(&["AU"]), ["ABSTRACT_API_KEY", "SLACK_WEBHOOK_URL"]);
+        assert_eq!(names(&["AU", "--output", "jsonl"]), ["ABSTRACT_API_KEY"]);
+        assert_eq!(names(&["AU", "--dry-run"]), ["ABSTRACT_API_KEY"]);
+        assert_eq!(names(&["--test-webhook"]), ["SLACK_WEBHOOK_URL"]);
+    }
+
+    #[test]
+    fn consecutive_days_are_merged() {
+        let holidays = [
+            holiday("Tet", "VN", "Vietnam", "2025-01-27"),
+            holiday("Tet", "VN", "Vietnam", "2025-01-25"),
+            holiday("Tet", "VN", "Vietnam", "2025-01-26"),
+            holiday("Tet", "VN", "Vietnam", "2025-01-29"),
+            holiday("New Year's Eve", "DE", "Germany", "2025-12-31"),
+            holiday("New Year's Day", "DE", "Germany", "2026-01-01"),
+        ];
+
+        let merged = merge_consecutive_days(&holidays);
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged[0].date, date("2025-01-25"));
+        assert_eq!(merged[0].last_date, Some(date("2025-01-27")));
+        assert_eq!(merged[0].display_date(), "Sat 25 Jan – Mon 27 Jan 2025");
+        assert_eq!(merged[1].display_date(), "Wed 29 Jan 2025");
+
+        let mut new_year = holiday("New Year", "DE", "Germany", "2025-12-31");
+        new_year.last_date = Some(date("2026-01-01"));
+        assert_eq!(new_year.display_date(), "Wed 31 Dec 2025 – Thu 1 Jan 2026");
+    }
+
+    #[test]
+    fn country_emoji_can_be_overridden() {
+        assert_eq!(
+            parse_country_emoji("Germany=:beer:").unwrap(),
+            ("DE".to_string(), "beer".to_string())
+        );
+        assert!(parse_country_emoji("DE=::").is_err());
+
+        let au = holiday("Christmas Day", "AU", "Australia", "2025-12-25");
+        let de = holiday("Christmas Day", "DE", "Germany", "2025-12-25");
+        let festive = options(&["AU", "--theme", "festive", "--country-emoji", "DE=beer"]);
+        assert_eq!(country_emoji(&au, &festive).as_deref(), Some("flag-au"));
+        assert_eq!(country_emoji(&de, &festive).as_deref(), Some("beer"));
+
+        let minimal = options(&["AU", "--theme", "minimal", "--country-emoji", "DE=beer"]);
+        assert_eq!(country_emoji(&de, &minimal), None);
+
+        // Slack's flag for the UK is "flag-gb", and an override for either code applies to both.
+        let uk = holiday("Christmas Day", "UK", "United Kingdom", "2025-12-25");
+        let gb = holiday("Christmas Day", "GB", "United Kingdom", "2025-12-25");
+        assert_eq!(country_emoji(&uk, &festive).as_deref(), Some("flag-gb"));
+        let crown = options(&["AU", "--theme", "festive", "--country-emoji", "uk=crown"]);
+        assert_eq!(country_emoji(&uk, &crown).as_deref(), Some("crown"));
+        assert_eq!(country_emoji(&gb, &crown).as_deref(), Some("crown"));
+    }
+
+    #[test]
+    fn plain_messages_drop_emoji_codes() {
+        assert_eq!(
+            strip_emoji_codes(":palm_tree: Holiday today at 10:30 :tada:\n:wave:  hi"),
+            "Holiday today at 10:30\nhi"
+        );
+
+        let options = options(&["AU", "--plain", "--terse"]);
+        assert_eq!(
+            note_text(DEFAULT_TERSE_TEXT, &options),
+            serde_json::json!({
+                "type": "plain_text",
+                "text": "Holiday today in some regions",
+                "emoji": false,
+            })
+        );
+    }
+
+    #[test]
+    fn footer_is_only_added_to_the_last_message() {
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+        ];
+        let options = options(&[
+            "AU",
+            "--message-per-country",
+            "--footer-text",
+            "Posted by the bot",
+        ]);
+        let messages = slack_messages(&holidays, &options);
+        assert_eq!(messages.len(), 2);
+
+        let last_block =
+            |message: &serde_json::Value| message["blocks"].as_array().unwrap().last().cloned();
+        assert_ne!(last_block(&messages[0]).unwrap()["type"], "context");
+        assert_eq!(
+            last_block(&messages[1]).unwrap(),
+            serde_json::json!({
+                "type": "context",
+                "elements": [{"type": "mrkdwn", "text": "Posted by the bot"}]
+            })
+        );
+    }
+
+    #[test]
+    fn message_per_country_sends_each_country_separately() {
+        let holidays = [
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Boxing Day", "AU", "Australia", "2025-12-26"),
+        ];
+
+        let messages = slack_messages(&holidays, &options(&["AU", "--message-per-country"]));
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].to_string().contains("Boxing Day"));
+        assert!(!messages[1].to_string().contains("Australia"));
+        assert!(messages[1].to_string().contains("Germany"));
+
+        assert_eq!(slack_messages(&holidays, &options(&["AU"])).len(), 1);
+    }
+
+    #[test]
+    fn compare_shows_who_is_off_and_who_is_working() {
+        let holidays = [holiday("Christmas Day", "AU", "Australia", "2025-12-25")];
+        let dates_by_country = [
+            ("AU".to_string(), vec![date("2025-12-25")]),
+            (
+                "in".to_string(),
+                vec![date("2025-12-25"), date("2025-12-26")],
+            ),
+        ];
+        let options = options(&["AU", "--theme", "minimal"]);
+
+        let texts = compare_blocks(&holidays, &dates_by_country, &options)
+            .iter()
+            .map(|b| b["text"]["text"].as_str().unwrap().to_string())
+            .collect_vec();
+        assert_eq!(
+            texts,
+            [
+                "Thu 25 Dec 2025\nOff: Australia (Christmas Day)\nWorking: India",
+                "Fri 26 Dec 2025\nOff: nobody\nWorking: India",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_country_codes_are_shown_as_is() {
+        let options = options(&["AU"]);
+
+        assert_eq!(country_name("de", &options), "Germany");
+        assert_eq!(country_name("uk", &options), "UK");
+        assert_eq!(country_name("qq", &options), "QQ");
+    }
+
+    #[test]
+    fn primary_name_puts_the_local_name_first() {
+        let mut h = holiday("Christmas Day", "DE", "Germany", "2025-12-25");
+        h.name_local = Some("Weihnachten".to_string());
+
+        assert_eq!(
+            holiday_text(&h, &options(&["DE"]), true),
+            "*Christmas Day* _(Weihnachten)_"
+        );
+        assert_eq!(
+            holiday_text(&h, &options(&["DE", "--primary-name", "local"]), true),
+            "*Weihnachten* _(Christmas Day)_"
+        );
+        // Only one name to choose from.
+        assert_eq!(
+            holiday_text(
+                &h,
+                &options(&["DE", "--primary-name", "local", "--name-style", "english"]),
+                true
+            ),
+            "*Christmas Day*"
+        );
+    }
+
+    #[test]
+    fn changes_since_the_last_run_are_noted_as_corrections() {
+        let path = env::temp_dir()
+            .join(format!(
+                "public-holiday-slackbot-{}-state.json",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+        let day = date("2025-12-26");
+        let boxing_day = [holiday("Boxing Day", "AU", "Australia", "2025-12-26")];
+        let st_stephens = [holiday("St. Stephen's Day", "IE", "Ireland", "2025-12-26")];
+
+        assert!(matches!(
+            compare_with_last_run(&path, day, &[]).unwrap(),
+            RunChanges::New
+        ));
+
+        save_run_state(&path, day, &boxing_day).unwrap();
+        assert!(matches!(
+            compare_with_last_run(&path, day, &boxing_day).unwrap(),
+            RunChanges::Unchanged
+        ));
+        assert!(matches!(
+            compare_with_last_run(&path, date("2025-12-25"), &boxing_day).unwrap(),
+            RunChanges::New
+        ));
+
+        let correction = compare_with_last_run(&path, day, &st_stephens)
+            .unwrap()
+            .correction()
+            .unwrap();
+        assert_eq!(
+            correction,
+            "added St. Stephen's Day (IE); removed Boxing Day (AU)"
+        );
+        assert_eq!(
+            correction_text("added Q&A (AU)", true),
+            "*Correction* to the earlier post: added Q&amp;A (AU)"
+        );
+        assert_eq!(
+            correction_text("added Q&A (AU)", false),
+            "Correction to the earlier post: added Q&A (AU)"
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_correction_is_sent_when_every_holiday_was_removed() {
+        let day = date("2025-12-25");
+        let state = temp_file("removed-state.json", "{}");
+        save_run_state(
+            &state,
+            day,
+            &[holiday("Christmas Day", "AU", "Australia", "2025-12-25")],
+        )
+        .unwrap();
+
+        let (api_url, api_requests) = mock_server_for(vec![api_response(&[])]);
+        let (slack_url, slack_request) =
+            mock_server("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let args = args(&[
+            "AU",
+            "--post-only-if-changed",
+            &state,
+            "--holidays-api-url",
+            &api_url,
+        ]);
+        with_slack_webhook_url(&slack_url, || run(&args, "key", day)).unwrap();
+        api_requests.join().unwrap();
+
+        let message: serde_json::Value =
+            serde_json::from_str(&slack_request.join().unwrap()).unwrap();
+        assert_eq!(
+            message["blocks"][1]["text"]["text"],
+            "*Correction* to the earlier post: removed Christmas Day (AU)"
+        );
+        assert_eq!(message["blocks"].as_array().unwrap().len(), 2);
+
+        // With the removal sent, there's nothing left to correct next time.
+        assert!(load_run_state(&state).unwrap()[&day.to_string()].is_empty());
+        assert!(slack_messages(&[], &options(&["AU"])).is_empty());
+
+        fs::remove_file(state).unwrap();
+    }
+
+    #[test]
+    fn mattermost_compat_uses_attachments() {
+        let holidays = [
+            holiday("Christmas Day", "DE", "Germany", "2025-12-25"),
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+        ];
+        let options = options(&[
+            "AU",
+            "--compat",
+            "mattermost",
+            "--footer-text",
+            "Posted by the bot",
+        ]);
+
+        let messages = slack_messages(&holidays, &options);
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert!(message.get("blocks").is_none());
+        assert_eq!(message["text"], options.theme.header_text(None));
+        assert_eq!(
+            message["attachments"],
+            serde_json::json!([
+                {
+                    "fallback": "Australia: Christmas Day",
+                    "title": "Australia",
+                    "text": "- Christmas Day",
+                },
+                {
+                    "fallback": "Germany: Christmas Day",
+                    "title": "Germany",
+                    "text": "- Christmas Day",
+                    "footer": "Posted by the bot",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn home_country_comes_first_and_is_marked() {
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Christmas Day", "NZ", "New Zealand", "2025-12-25"),
+        ];
+        let options = options(&["AU", "--home", "nz"]);
+
+        let message = build_slack_payload(&holidays, &options);
+        let texts = message["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|b| b["text"]["text"].as_str())
+            .collect_vec();
+        assert_eq!(texts[1..], [":house: *New Zealand*", "_Australia_"]);
+
+        let message = attachments_message(&holidays, &options);
+        assert_eq!(message["attachments"][0]["title"], "New Zealand (home)");
+        assert_eq!(message["attachments"][1]["title"], "Australia");
+
+        let gb = holiday("Christmas Day", "GB", "United Kingdom", "2025-12-25");
+        assert!(!is_home(&gb, &options));
+        let uk_home = message_options(&args(&["UK", "--home", "uk"]), &[], None, None);
+        assert!(is_home(&gb, &uk_home));
+    }
+
+    #[test]
+    fn names_file_keeps_only_the_holidays_it_lists() {
+        let path = temp_file(
+            "names.txt",
+            "# Days off\nchristmas day\n\n  WEIHNACHTEN  \n",
+        );
+        let names = load_holiday_names(&path).unwrap();
+
+        let mut christi_himmelfahrt = holiday("Ascension Day", "DE", "Germany", "2025-05-29");
+        christi_himmelfahrt.name_local = Some("Christi Himmelfahrt".to_string());
+        let mut weihnachten = holiday("Christmas", "DE", "Germany", "2025-12-25");
+        weihnachten.name_local = Some("Weihnachten".to_string());
+        let holidays = [
+            holiday("Christmas Day", "AU", "Australia", "2025-12-25"),
+            holiday("Boxing Day", "AU", "Australia", "2025-12-26"),
+            christi_himmelfahrt,
+            weihnachten,
+        ];
+
+        // Either name can be listed, in any case, and comments aren't names.
+        let kept = filter_holidays(&holidays, Some(&names), &args(&["AU,DE"]))
+            .into_iter()
+            .map(|h| (h.country.unwrap(), h.name))
+            .collect_vec();
+        assert_eq!(
+            kept,
+            [
+                ("AU".to_string(), "Christmas Day".to_string()),
+                ("DE".to_string(), "Christmas".to_string()),
+            ]
+        );
+        assert_eq!(filter_holidays(&holidays, None, &args(&["AU,DE"])).len(), 4);
+
+        assert!(load_holiday_names("/nonexistent/names.txt").is_err());
+    }
+
+    #[test]
+    fn responses_over_the_size_limit_are_rejected() {
+        let response = |body: &str| {
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .parse::<ureq::Response>()
+            .unwrap()
+        };
+
+        assert_eq!(read_response(response("€€"), 6).unwrap(), "€€");
+        // The limit falls in the middle of the second "€".
+        let e = read_response(response("€€"), 4).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "response is larger than the limit of 4 bytes"
+        );
+    }
+
+    #[test]
+    fn country_names_can_be_overridden() {
+        assert_eq!(
+            parse_country_name("gb= Britain ").unwrap(),
+            ("GB".to_string(), "Britain".to_string())
+        );
+        assert!(parse_country_name("GB=").is_err());
+
+        let options = options(&["DE", "--country-name", "DE=Deutschland"]);
+        let bavaria = holiday("Epiphany", "DE", "Germany - Bavaria", "2025-01-06");
+        assert_eq!(
+            display_location(&bavaria, &options).as_deref(),
+            Some("Deutschland - Bavaria")
+        );
+        assert_eq!(country_name("de", &options), "Deutschland");
+
+        let britain = message_options(
+            &args(&["UK", "--country-name", "UK=Britain"]),
+            &[],
+            None,
+            None,
+        );
+        let gb = holiday("Christmas Day", "GB", "United Kingdom", "2025-12-25");
+        assert_eq!(display_location(&gb, &britain).as_deref(), Some("Britain"));
+        assert_eq!(country_name("uk", &britain), "Britain");
+        assert_eq!(country_name("gb", &britain), "Britain");
+    }
+
+    #[test]
+    fn several_outputs_are_all_sent_in_one_run() {
+        let christmas =
+            || api_response(&[holiday("Christmas Day", "AU", "Australia", "2025-12-25")]);
+        let (url, requests) = mock_server_for(vec![christmas(), christmas()]);
+        let out = temp_file("several-outputs.jsonl", "");
+        let preview = temp_file("several-outputs.html", "");
+        let dates_by_country = [("AU".to_string(), vec![date("2025-12-25")])];
+        let outcome = |preview: &str| {
+            let args = args(&[
+                "AU",
+                "--output",
+                "slack,jsonl",
+                "--preview-html",
+                preview,
+                "--out",
+                &out,
+                "--holidays-api-url",
+                &url,
+            ]);
+            assert!(!posts_to_slack(&args));
+            fetch_and_send(&args, "key", date("2025-12-25"), &dates_by_country, &mut 0).unwrap()
+        };
+
+        outcome(&preview).result.unwrap();
+        assert!(fs::read_to_string(&preview)
+            .unwrap()
+            .contains("Christmas Day"));
+        assert_eq!(read_jsonl(&out).len(), 1);
+
+        // A directory can't be written to, but the JSON Lines still are.
+        fs::write(&out, "").unwrap();
+        let dir = env::temp_dir().to_string_lossy().into_owned();
+        let e = outcome(&dir).result.unwrap_err();
+        requests.join().unwrap();
+        assert!(
+            format!("{:#}", e).starts_with("1 of 2 outputs failed: slack: "),
+            "{:#}",
+            e
+        );
+        assert_eq!(read_jsonl(&out).len(), 1);
+
+        fs::remove_file(out).unwrap();
+        fs::remove_file(preview).unwrap();
+
+        assert!(posts_to_slack(&args(&["AU", "--output", "slack,jsonl"])));
+        assert!(!posts_to_slack(&args(&[
+            "AU",
+            "--output",
+            "jsonl,slack-json"
+        ])));
+    }
+
+    #[test]
+    fn countries_can_be_shown_as_codes() {
+        let bavaria = holiday("Epiphany", "DE", "Germany - Bavaria", "2025-01-06");
+        let location = |argv: &[&str]| display_location(&bavaria, &options(argv));
+
+        assert_eq!(location(&["DE"]).as_deref(), Some("Germany - Bavaria"));
+        assert_eq!(
+            location(&["DE", "--country-display", "short"]).as_deref(),
+            Some("DEU - Bavaria")
+        );
+        assert_eq!(
+            location(&["DE", "--country-display", "code"]).as_deref(),
+            Some("DE - Bavaria")
+        );
+        assert_eq!(
+            country_name("de", &options(&["DE", "--country-display", "short"])),
+            "DEU"
+        );
+    }
+
+    #[test]
+    fn weekend_holidays_are_observed_on_a_weekday() {
+        let us = observance_rule("US").unwrap();
+        assert_eq!(
+            us.observed_date(date("2026-07-04")),
+            Some(date("2026-07-03"))
+        );
+        assert_eq!(
+            us.observed_date(date("2026-07-05")),
+            Some(date("2026-07-06"))
+        );
+        assert_eq!(us.observed_date(date("2026-07-06")), None);
+        assert_eq!(us.source_dates(&[date("2026-07-03")]), [date("2026-07-04")]);
+
+        let holidays = vec![holiday(
+            "Independence Day",
+            "US",
+            "United States",
+            "2026-07-04",
+        )];
+        let observed = us.apply(holidays, &[date("2026-07-03")]);
+        assert_eq!(observed.len(), 1);
+        assert_eq!(observed[0].name, "Independence Day (observed)");
+        assert_eq!(observed[0].date, date("2026-07-03"));
+
+        let uk = observance_rule("uk").unwrap();
+        assert_eq!(
+            uk.observed_date(date("2027-12-25")),
+            Some(date("2027-12-27"))
+        );
+        assert!(observance_rule("SA").is_none());
+    }
+
+    #[test]
+    fn jobs_file_jobs_are_all_fetched_in_one_run() {
+        let path = temp_file(
+            "jobs.json",
+            r#"[{"country": "AU", "date": "2025-01-26"}, {"country": "Germany"}]"#,
+        );
+        let (url, requests) = mock_server_for(vec![
+            api_response(&[holiday("Australia Day", "AU", "Australia", "2025-01-26")]),
+            api_response(&[holiday("Christmas Day", "DE", "Germany", "2025-12-25")]),
+        ]);
+        let out = temp_file("jobs.jsonl", "");
+
+        let mut args = args(&[
+            "--jobs-file",
+            &path,
+            "--output",
+            "jsonl",
+            "--out",
+            &out,
+            "--holidays-api-url",
+            &url,
+        ]);
+        add_jobs(&mut args).unwrap();
+        run(&args, "key", date("2025-12-25")).unwrap();
+
+        let requested = requests
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|(request_line, _)| request_line)
+            .collect_vec();
+        assert!(
+            requested[0].contains("country=AU&year=2025&month=1&day=26"),
+            "{}",
+            requested[0]
+        );
+        assert!(
+            requested[1].contains("country=DE&year=2025&month=12&day=25"),
+            "{}",
+            requested[1]
+        );
+        assert_eq!(
+            read_jsonl(&out).into_iter().map(|h| h.name).collect_vec(),
+            ["Australia Day", "Christmas Day"]
+        );
+
+        let path = temp_file(
+            "bad-jobs.json",
+            r#"[{"country": "AU"}, {"country": "AU", "date": "26/01/2025"}]"#,
+        );
+        let e = load_jobs(&path).unwrap_err();
+        assert!(format!("{:#}", e).starts_with("job 2: "), "{:#}", e);
+    }
+
+    #[test]
+    fn local_dates_use_each_country_time_zone() {
+        let args = args(&[
+            "AU",
+            "--local-dates",
+            "--now",
+            "2025-12-25T09:00:00+11:00",
+            "--timezone",
+            "US=America/Los_Angeles",
+        ]);
+
+        assert_eq!(country_today("AU", &args), Some(date("2025-12-25")));
+        assert_eq!(country_today("NZ", &args), Some(date("2025-12-25")));
+        assert_eq!(country_today("UK", &args), Some(date("2025-12-24")));
+        assert_eq!(country_today("us", &args), Some(date("2025-12-24")));
+        assert_eq!(country_today("QQ", &args), None);
+    }
+
+    #[test]
+    fn holiday_types_can_be_relabelled_and_ordered() {
+        let options = options(&[
+            "DE",
+            "--show-types",
+            "--type-label",
+            "Local holiday = Regional",
+            "--type-order",
+            "local holiday,National",
+        ]);
+
+        let mut epiphany = holiday("Epiphany", "DE", "Germany - Bavaria", "2025-01-06");
+        epiphany.r#type = Some("Local holiday".to_string());
+        assert_eq!(
+            holiday_text(&epiphany, &options, false),
+            "Epiphany [Regional]"
+        );
+
+        assert_eq!(options.type_rank(Some("Local holiday")), 0);
+        assert_eq!(options.type_rank(Some("National")), 1);
+        assert_eq!(options.type_rank(Some("Observance")), 2);
+        assert_eq!(options.type_rank(None), 2);
+
+        let mut new_year = holiday("New Year's Day", "DE", "Germany", "2025-01-01");
+        new_year.r#type = Some("National".to_string());
+        let mut regional = holiday("Regional Day", "DE", "Germany", "2025-01-01");
+        regional.r#type = Some("Local holiday".to_string());
+        let message = attachments_message(&[new_year, regional], &options);
+        assert_eq!(
+            message["attachments"][0]["text"],
+            "- Regional Day [Regional]\n- New Year's Day [National]"
+        );
+
+        assert!(parse_type_label("Regional").is_err());
+    }
+
+    #[test]
+    fn warn_threshold_is_a_count_or_a_percentage() {
+        let count = parse_failure_threshold("2").unwrap();
+        assert!(!count.exceeded(2, 10));
+        assert!(count.exceeded(3, 10));
+
+        let percent = parse_failure_threshold(" 25% ").unwrap();
+        assert!(!percent.exceeded(1, 4));
+        assert!(percent.exceeded(2, 4));
+        assert!(!percent.exceeded(0, 0));
+
+        assert!(parse_failure_threshold("a quarter").is_err());
+        assert!(parse_failure_threshold("-1").is_err());
+    }
+
+    #[test]
+    fn sample_payload_covers_several_countries_and_days() {
+        let holidays = sample_holidays();
+        let countries = holidays
+            .iter()
+            .filter_map(|h| h.country.as_deref())
+            .unique()
+            .collect_vec();
+        assert_eq!(countries, ["AU", "DE", "ES"]);
+        assert!(holidays.iter().any(|h| h.date != holidays[0].date));
+
+        let message = build_slack_payload(&holidays, &options(&["AU"]));
+        assert_eq!(validate_slack_payload(&message), Vec::<String>::new());
+        let text = message.to_string();
+        for expected in [
+            "Australia",
+            "Germany",
+            "Spain - Catalonia",
+            "Weihnachten",
+            "Sant Esteve",
+        ] {
+            assert!(text.contains(expected), "{}", expected);
+        }
+    }
+
+    #[test]
+    fn calendar_links_create_an_all_day_event() {
+        assert_eq!(
+            percent_encode("St. Stephen's Day"),
+            "St.%20Stephen%27s%20Day"
+        );
+        assert_eq!(percent_encode("Día & Noche"), "D%C3%ADa%20%26%20Noche");
+
+        let options = options(&["AU", "--add-to-calendar-links"]);
+        let mut tet = holiday("Tet", "VN", "Vietnam", "2025-01-29");
+        tet.last_date = Some(date("2025-01-31"));
+        assert_eq!(
+            calendar_link(&tet, &options),
+            "https://calendar.google.com/calendar/render?action=TEMPLATE&text=Tet&dates=20250129/20250201"
+        );
+    }
+
+    #[test]
+    fn dates_are_chosen_from_now_in_each_mode() {
+        // A Thursday in Australia, and still Christmas Eve in the US.
+        let now = ["--now", "2025-12-25T09:00:00+11:00"];
+        let dates = |argv: &[&str]| {
+            let args = args(&[argv, &now].concat());
+            dates_by_country(&args, run_date(&args))
+        };
+        let days = |days: &[&str]| days.iter().map(|d| date(d)).collect_vec();
+
+        assert_eq!(
+            dates(&["AU,NZ@2025-02-06"]),
+            [
+                ("AU".to_string(), days(&["2025-12-25"])),
+                ("NZ".to_string(), days(&["2025-02-06"])),
+            ]
+        );
+        assert_eq!(
+            dates(&["AU", "--date", "2025-01-26"]),
+            [("AU".to_string(), days(&["2025-01-26"]))]
+        );
+        assert_eq!(
+            dates(&["AU", "--this-weekend"]),
+            [("AU".to_string(), days(&["2025-12-27", "2025-12-28"]))]
+        );
+
+        let month = dates(&["AU", "--this-month"]);
+        assert_eq!(month[0].1.len(), 31);
+        assert_eq!(month[0].1[0], date("2025-12-01"));
+
+        assert_eq!(
+            dates(&[
+                "AU,US",
+                "--local-dates",
+                "--timezone",
+                "US=America/Los_Angeles"
+            ]),
+            [
+                ("AU".to_string(), days(&["2025-12-25"])),
+                ("US".to_string(), days(&["2025-12-24"])),
+            ]
+        );
+        assert_eq!(dates(&["AU,NZ,US", "--limit-countries", "2"]).len(), 2);
+    }
+
+    #[test]
+    fn now_can_be_pinned() {
+        let with_offset = parse_now("2025-12-25T09:00:00+11:00").unwrap();
+        assert_eq!(with_offset.offset().local_minus_utc(), 11 * 60 * 60);
+        assert_eq!(with_offset.date_naive(), date("2025-12-25"));
+
+        // Without an offset it's in the local time zone.
+        let local = parse_now("2025-12-25T12:00:00").unwrap();
+        assert_eq!(local.date_naive(), date("2025-12-25"));
+
+        assert!(parse_now("2025-12-25").is_err());
+
+        let pinned = args(&["AU", "--now", "2025-12-25T09:00:00+11:00"]);
+        assert_eq!(now(&pinned).to_rfc3339(), "2025-12-25T09:00:00+11:00");
+    }
+
+    #[test]
+    fn diff_against_counts_the_differences_from_a_snapshot() {
+        let path = temp_file(
+            "snapshot.json",
+            r#"[
+                {"name": "Christmas Day", "country": "AU", "location": "Australia", "type": "National", "date": "12/25/2025"},
+                {"name": "Boxing Day", "country": "AU", "location": "Australia", "type": "National", "date": "12/26/2025"}
+            ]"#,
+        );
+        let christmas = holiday("Christmas Day", "AU", "Australia", "2025-12-25");
+        let boxing_day = holiday("Boxing Day", "AU", "Australia", "2025-12-26");
+
+        assert!(diff_against_snapshot(&path, &[christmas.clone(), boxing_day]).is_ok());
+
+        let mut observance = christmas;
+        observance.r#type = Some("Observance".to_string());
+        let new_years_eve = holiday("New Year's Eve", "AU", "Australia", "2025-12-31");
+        let e = diff_against_snapshot(&path, &[observance, new_years_eve]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("3 differences from snapshot {}", path)
+        );
+
+        assert!(diff_against_snapshot("/nonexistent/snapshot.json", &[]).is_err());
+    }
+
+    #[test]
+    fn fetch_errors_are_classified_by_kind() {
+        let fetch = |url: &str| HolidayError::from(ureq::get(url).call().unwrap_err());
+
+        let (url, _) = mock_server(
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3\r\nContent-Length: 0\r\n\r\n",
+        );
+        assert!(matches!(
+            fetch(&url),
+            HolidayError::RateLimited { retry_after: Some(d) } if d == Duration::from_secs(3)
+        ));
+
+        let (url, _) = mock_server("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        assert!(matches!(fetch(&url), HolidayError::NotFound));
+
+        let (url, _) = mock_server("HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n");
+        assert!(matches!(fetch(&url), HolidayError::Status(401)));
+
+        // Connections are queued, but never answered.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let e = ureq::AgentBuilder::new()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .get(&url)
+            .call()
+            .unwrap_err();
+        assert_eq!(error_kind(&HolidayError::from(e)), "timeout");
+
+        drop(listener);
+        assert_eq!(error_kind(&fetch(&url)), "transport");
+    }
+
+    #[test]
+    fn flush_every_posts_each_batch_as_it_is_fetched() {
+        let christmas = |country, location| {
+            api_response(&[holiday("Christmas Day", country, location, "2025-12-25")])
+        };
+        let (api_url, api_requests) = mock_server_for(vec![
+            christmas("AU", "Australia"),
+            christmas("NZ", "New Zealand"),
+        ]);
+        let ok = "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 2\r\n\r\nok";
+        let (slack_url, slack_requests) = mock_server_for(vec![ok.to_string(), ok.to_string()]);
+
+        let args = args(&[
+            "AU,NZ",
+            "--flush-every",
+            "1",
+            "--holidays-api-url",
+            &api_url,
+        ]);
+        with_slack_webhook_url(&slack_url, || run(&args, "key", date("2025-12-25"))).unwrap();
+        api_requests.join().unwrap();
+
+        let posts = slack_requests
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|(_, body)| body)
+            .collect_vec();
+        assert_eq!(posts.len(), 2);
+        assert!(
+            posts[0].contains("Australia") && !posts[0].contains("New Zealand"),
+            "{}",
+            posts[0]
+        );
+        assert!(
+            posts[1].contains("New Zealand") && !posts[1].contains("Australia"),
+            "{}",
+            posts[1]
+        );
+    }
+
+    #[test]
+    fn flush_every_needs_a_positive_batch_size_and_no_per_run_options() {
+        let parse = |argv: &[&str]| {
+            Args::try_parse_from(["public-holiday-slackbot"].iter().chain(argv))
+                .map(|args| args.flush_every.map(NonZeroUsize::get))
+        };
+
+        assert_eq!(parse(&["AU", "NZ", "--flush-every", "1"]).unwrap(), Some(1));
+        assert!(parse(&["AU", "--flush-every", "0"]).is_err());
+        for option in [
+            "--out=holidays.jsonl",
+            "--idempotency-file=sent.txt",
+            "--preview-html=preview.html",
+            "--result-json",
+            "--warn-threshold=1",
+        ] {
+            let e = parse(&["AU", "--flush-every", "2", option]).unwrap_err();
+            assert_eq!(
+                e.kind(),
+                clap::error::ErrorKind::ArgumentConflict,
+                "{}",
+                option
+            );
+        }
+    }
 }